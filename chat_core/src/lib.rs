@@ -19,6 +19,10 @@ pub struct User {
     #[serde(skip)]
     pub password_hash: Option<String>,
     pub created_at: DateTime<Utc>,
+    // session the access token was issued for, only set in token claims
+    #[sqlx(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<i64>,
 }
 
 #[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
@@ -51,6 +55,38 @@ pub enum ChatType {
     PublicChannel,
 }
 
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "workspace_role", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum WorkspaceRole {
+    Owner,
+    Admin,
+    Member,
+    Guest,
+}
+
+impl WorkspaceRole {
+    /// owner and admins manage the workspace and moderate every chat in it
+    pub fn is_admin(&self) -> bool {
+        matches!(self, Self::Owner | Self::Admin)
+    }
+}
+
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "chat_role", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum ChatRole {
+    Owner,
+    Admin,
+    Member,
+}
+
+impl ChatRole {
+    pub fn is_admin(&self) -> bool {
+        matches!(self, Self::Owner | Self::Admin)
+    }
+}
+
 #[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Chat {
@@ -63,6 +99,12 @@ pub struct Chat {
     pub members: Vec<i64>,
     #[serde(alias = "createdAt", alias = "created_at")]
     pub created_at: DateTime<Utc>,
+    #[serde(default, alias = "archivedAt", alias = "archived_at")]
+    pub archived_at: Option<DateTime<Utc>>,
+    /// default time to live of new messages
+    #[sqlx(default)]
+    #[serde(default, alias = "messageTtlSecs", alias = "message_ttl_secs")]
+    pub message_ttl_secs: Option<i32>,
 }
 
 #[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
@@ -73,10 +115,112 @@ pub struct Message {
     pub chat_id: i64,
     #[serde(alias = "senderId", alias = "sender_id")]
     pub sender_id: i64,
+    /// markdown source, see `content_html` for the rendered message
     pub content: String,
+    /// sanitized html of the content, safe to insert into a page
+    #[sqlx(default)]
+    #[serde(default, alias = "contentHtml", alias = "content_html")]
+    pub content_html: String,
     pub files: Vec<String>,
     #[serde(alias = "createdAt", alias = "created_at")]
     pub created_at: DateTime<Utc>,
+    #[serde(default, alias = "editedAt", alias = "edited_at")]
+    pub edited_at: Option<DateTime<Utc>>,
+    /// set on tombstones of deleted messages, their content and files are cleared
+    #[serde(default, alias = "deletedAt", alias = "deleted_at")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// the message this one replies to in a thread
+    #[serde(default, alias = "parentId", alias = "parent_id")]
+    pub parent_id: Option<i64>,
+    #[serde(default, alias = "replyCount", alias = "reply_count")]
+    pub reply_count: i32,
+    #[serde(default, alias = "lastReplyAt", alias = "last_reply_at")]
+    pub last_reply_at: Option<DateTime<Utc>>,
+    /// the message is deleted once it expires
+    #[sqlx(default)]
+    #[serde(default, alias = "expiresAt", alias = "expires_at")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// nonce the sender supplied, only set on the message returned to the sender and in
+    /// the new message event
+    #[sqlx(default)]
+    #[serde(default, alias = "clientId", alias = "client_id")]
+    pub client_id: Option<String>,
+    /// snapshot of the message this one quotes or forwards
+    #[sqlx(default, json)]
+    #[serde(default)]
+    pub reference: Option<MessageReference>,
+    #[sqlx(default, json)]
+    #[serde(default)]
+    pub reactions: Vec<ReactionCount>,
+}
+
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ReferenceKind {
+    Quote,
+    Forward,
+}
+
+/// The referenced message as it was when it was quoted or forwarded.
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageReference {
+    pub kind: ReferenceKind,
+    pub message_id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub content: String,
+    #[serde(default)]
+    pub content_html: String,
+    pub files: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The last message a user has read in a chat.
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadMarker {
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub last_read_message_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: i64,
+    pub user_ids: Vec<i64>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionChanged {
+    pub message_id: i64,
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub emoji: String,
+    /// false if the reaction was removed
+    pub added: bool,
+    pub reactions: Vec<ReactionCount>,
+}
+
+/// A message pinned to the top of a chat.
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Pin {
+    pub chat_id: i64,
+    pub message_id: i64,
+    pub pinned_by: i64,
+    pub pinned_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PinsUpdated {
+    pub chat_id: i64,
+    /// all pins of the chat, oldest first
+    pub pins: Vec<Pin>,
 }
 
 #[cfg(test)]
@@ -91,6 +235,7 @@ impl User {
             email: email.to_string(),
             password_hash: None,
             created_at: Utc::now(),
+            sid: None,
         }
     }
 }
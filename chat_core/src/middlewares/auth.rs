@@ -42,7 +42,7 @@ where
             }
         };
 
-    let req = match state.verify(&token) {
+    let req = match state.verify(&token).await {
         Ok(user) => {
             let mut req = Request::from_parts(parts, body);
             req.extensions_mut().insert(user);
@@ -77,7 +77,7 @@ mod tests {
     impl TokenVerify for AppState {
         type Error = ();
 
-        fn verify(&self, token: &str) -> Result<User, Self::Error> {
+        async fn verify(&self, token: &str) -> Result<User, Self::Error> {
             self.0.dk.verify(token).map_err(|_| ())
         }
     }
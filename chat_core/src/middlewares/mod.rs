@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{fmt, future::Future};
 
 use axum::{middleware::from_fn, Router};
 use request_id::set_request_id;
@@ -15,7 +15,7 @@ const REQUEST_ID_HEADER: &str = "x-request-id";
 
 pub trait TokenVerify {
     type Error: fmt::Debug;
-    fn verify(&self, token: &str) -> Result<User, Self::Error>;
+    fn verify(&self, token: &str) -> impl Future<Output = Result<User, Self::Error>> + Send;
 }
 
 pub use auth::verify_token;
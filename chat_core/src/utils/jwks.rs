@@ -0,0 +1,49 @@
+use jwt_simple::{
+    prelude::*,
+    reexports::ct_codecs::{Base64UrlSafeNoPadding, Decoder, Encoder},
+    JWTError,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// JSON Web Key Set (RFC 7517), as served on `/.well-known/jwks.json`
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+/// An Ed25519 public key in JWK form (RFC 8037)
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    #[serde(default)]
+    pub alg: Option<String>,
+    #[serde(default, rename = "use")]
+    pub key_use: Option<String>,
+    pub kid: String,
+    pub x: String,
+}
+
+impl Jwk {
+    pub fn new(kid: &str, key: &Ed25519PublicKey) -> Self {
+        let x = Base64UrlSafeNoPadding::encode_to_string(key.to_bytes())
+            .expect("encode public key should work");
+        Self {
+            kty: "OKP".to_string(),
+            crv: "Ed25519".to_string(),
+            alg: Some("EdDSA".to_string()),
+            key_use: Some("sig".to_string()),
+            kid: kid.to_string(),
+            x,
+        }
+    }
+
+    pub fn to_public_key(&self) -> Result<Ed25519PublicKey, jwt_simple::Error> {
+        if self.kty != "OKP" || self.crv != "Ed25519" {
+            return Err(JWTError::InvalidPublicKey.into());
+        }
+        let raw = Base64UrlSafeNoPadding::decode_to_vec(&self.x, None)?;
+        Ed25519PublicKey::from_bytes(&raw)
+    }
+}
@@ -1,17 +1,32 @@
-use crate::User;
-use jwt_simple::prelude::*;
+use std::collections::HashMap;
 
-const JWT_DURATION: u64 = 60 * 60 * 24 * 7;
+use crate::{Jwk, Jwks, User};
+use jwt_simple::{prelude::*, JWTError};
+
+/// access tokens are short-lived, clients renew them with a refresh token
+pub const JWT_DURATION: u64 = 60 * 15;
 const JWT_ISS: &str = "chat_server";
 const JWT_AUD: &str = "chat_web";
 
 pub struct EncodingKey(Ed25519KeyPair);
-pub struct DecodingKey(Ed25519PublicKey);
+
+/// A keyring of public keys indexed by key id, so tokens signed by retired keys
+/// keep working while a new signing key is rolled out.
+#[derive(Default)]
+pub struct DecodingKey(HashMap<String, Ed25519PublicKey>);
 
 impl EncodingKey {
+    /// Load the signing key. Its key id defaults to the thumbprint of the public key.
     pub fn load(pem: &str) -> Result<Self, jwt_simple::Error> {
         let key = Ed25519KeyPair::from_pem(pem)?;
-        Ok(Self(key))
+        let kid = key.public_key().sha1_thumbprint();
+        Ok(Self(key.with_key_id(&kid)))
+    }
+    pub fn with_kid(self, kid: &str) -> Self {
+        Self(self.0.with_key_id(kid))
+    }
+    pub fn kid(&self) -> &str {
+        self.0.key_id().as_deref().unwrap_or_default()
     }
     pub fn sign(&self, user: impl Into<User>) -> Result<String, jwt_simple::Error> {
         let claims = Claims::with_custom_claims(user.into(), Duration::from_secs(JWT_DURATION));
@@ -21,9 +36,45 @@ impl EncodingKey {
 }
 
 impl DecodingKey {
+    /// Load a keyring with a single key, the key id is the thumbprint of the key.
     pub fn load(pem: &str) -> Result<Self, jwt_simple::Error> {
-        Ok(Self(Ed25519PublicKey::from_pem(pem)?))
+        let mut dk = Self::default();
+        dk.add_pem(pem, None)?;
+        Ok(dk)
     }
+
+    pub fn add_pem(&mut self, pem: &str, kid: Option<&str>) -> Result<(), jwt_simple::Error> {
+        let key = Ed25519PublicKey::from_pem(pem)?;
+        let kid = kid.map_or_else(|| key.sha1_thumbprint(), |v| v.to_string());
+        self.0.insert(kid, key);
+        Ok(())
+    }
+
+    pub fn add_jwks(&mut self, jwks: &Jwks) -> Result<(), jwt_simple::Error> {
+        for jwk in &jwks.keys {
+            self.0.insert(jwk.kid.clone(), jwk.to_public_key()?);
+        }
+        Ok(())
+    }
+
+    pub fn to_jwks(&self) -> Jwks {
+        let mut keys: Vec<_> = self.0.iter().map(|(kid, key)| Jwk::new(kid, key)).collect();
+        keys.sort_by(|a, b| a.kid.cmp(&b.kid));
+        Jwks { keys }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether the key the token claims to be signed with is in the keyring.
+    pub fn has_key_for(&self, token: &str) -> bool {
+        match Token::decode_metadata(token) {
+            Ok(metadata) => metadata.key_id().is_none_or(|kid| self.0.contains_key(kid)),
+            Err(_) => true,
+        }
+    }
+
     pub fn verify(&self, token: &str) -> Result<User, jwt_simple::Error> {
         let opts = VerificationOptions {
             allowed_issuers: Some(HashSet::from_iter(vec![JWT_ISS.to_string()])),
@@ -31,7 +82,24 @@ impl DecodingKey {
             ..VerificationOptions::default()
         };
 
-        let claims = self.0.verify_token::<User>(token, Some(opts))?;
+        let metadata = Token::decode_metadata(token)?;
+        let claims = match metadata.key_id() {
+            Some(kid) => {
+                let key = self.0.get(kid).ok_or(JWTError::KeyIdentifierMismatch)?;
+                key.verify_token::<User>(token, Some(opts))?
+            }
+            // tokens issued before key ids were introduced, try every key
+            None => {
+                let mut ret = Err(JWTError::InvalidSignature.into());
+                for key in self.0.values() {
+                    ret = key.verify_token::<User>(token, Some(opts.clone()));
+                    if ret.is_ok() {
+                        break;
+                    }
+                }
+                ret?
+            }
+        };
         Ok(claims.custom)
     }
 }
@@ -39,8 +107,9 @@ impl DecodingKey {
 mod tests {
 
     use anyhow::Result;
+    use jwt_simple::{prelude::*, reexports::serde_json};
 
-    use crate::User;
+    use crate::{Jwk, Jwks, User};
 
     #[tokio::test]
     async fn jwt_verify_should_work() -> Result<()> {
@@ -58,4 +127,32 @@ mod tests {
         assert_eq!(user, user2);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn jwt_verify_with_rotated_keys_should_work() -> Result<()> {
+        let encoding_pem = include_str!("../../fixtures/encoding.pem");
+        let decoding_pem = include_str!("../../fixtures/decoding.pem");
+
+        let old_ek = super::EncodingKey::load(encoding_pem)?;
+        let new_ek = super::EncodingKey(Ed25519KeyPair::generate()).with_kid("2024-12");
+        let mut dk = super::DecodingKey::load(decoding_pem)?;
+
+        let user = User::new(1, "test", "test@test.com");
+        let old_token = old_ek.sign(user.clone())?;
+        let new_token = new_ek.sign(user.clone())?;
+        assert!(!dk.has_key_for(&new_token));
+        assert!(dk.verify(&new_token).is_err());
+
+        // publish the new key through jwks, old tokens keep working
+        let jwks = Jwks {
+            keys: vec![Jwk::new(new_ek.kid(), &new_ek.0.public_key())],
+        };
+        let jwks: Jwks = serde_json::from_str(&serde_json::to_string(&jwks)?)?;
+        dk.add_jwks(&jwks)?;
+        assert!(dk.has_key_for(&new_token));
+        assert_eq!(dk.verify(&old_token)?, user);
+        assert_eq!(dk.verify(&new_token)?, user);
+        assert_eq!(dk.to_jwks().keys.len(), 2);
+        Ok(())
+    }
 }
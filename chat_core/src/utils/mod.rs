@@ -1,3 +1,5 @@
+mod jwks;
 mod jwt;
 
-pub use jwt::{DecodingKey, EncodingKey};
+pub use jwks::{Jwk, Jwks};
+pub use jwt::{DecodingKey, EncodingKey, JWT_DURATION};
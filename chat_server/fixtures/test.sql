@@ -11,21 +11,55 @@ INSERT INTO users(ws_id, email, username, password_hash)
 (1, 'daisy@acme.org', 'Daisy Chen', '$argon2id$v=19$m=19456,t=2,p=1$MxGhY+ib/kplwBPLa7u2ug$c5h9u7Sc8Px8J5+qgNdOjSY7ZJO2QN4rugKpapGW4XU');
 -- insert 4 chats
 -- insert public/private channel
-INSERT INTO chats(ws_id, name, chat_type, members)
-  VALUES (1, 'general', 'public_channel', '{1,2,3,4,5}'),
-(1, 'private', 'private_channel', '{1,2,3}');
+INSERT INTO chats(ws_id, name, chat_type)
+  VALUES (1, 'general', 'public_channel'),
+(1, 'private', 'private_channel');
 -- insert unnamed chat
-INSERT INTO chats(ws_id, chat_type, members)
-  VALUES (1, 'single', '{1,2}'),
-(1, 'group', '{1,3,4}');
-INSERT INTO messages(chat_id, sender_id, content)
-  VALUES (1, 1, 'Hello, world!'),
-(1, 2, 'Hi, there!'),
-(1, 3, 'How are you?'),
-(1, 4, 'I am fine, thank you!'),
-(1, 5, 'Good to hear that!'),
-(1, 1, 'Hello, world!'),
-(1, 2, 'Hi, there!'),
-(1, 3, 'How are you?'),
-(1, 1, 'Hello, world!'),
-(1, 1, 'Hello, world!');
+INSERT INTO chats(ws_id, chat_type, dm_key)
+  VALUES (1, 'single', '1:2'),
+(1, 'group', NULL);
+-- Ivena created general, Alice created private and Charlie the group
+INSERT INTO chat_members(chat_id, user_id, role)
+  VALUES (1, 1, 'owner'),
+(1, 2, 'member'),
+(1, 3, 'member'),
+(1, 4, 'member'),
+(1, 5, 'member'),
+(2, 1, 'member'),
+(2, 2, 'owner'),
+(2, 3, 'member'),
+(3, 1, 'member'),
+(3, 2, 'member'),
+(4, 1, 'member'),
+(4, 3, 'owner'),
+(4, 4, 'member');
+INSERT INTO messages(chat_id, sender_id, content, content_html)
+  VALUES (1, 1, 'Hello, world!', '<p>Hello, world!</p>'),
+(1, 2, 'Hi, there!', '<p>Hi, there!</p>'),
+(1, 3, 'How are you?', '<p>How are you?</p>'),
+(1, 4, 'I am fine, thank you!', '<p>I am fine, thank you!</p>'),
+(1, 5, 'Good to hear that!', '<p>Good to hear that!</p>'),
+(1, 1, 'Hello, world!', '<p>Hello, world!</p>'),
+(1, 2, 'Hi, there!', '<p>Hi, there!</p>'),
+(1, 3, 'How are you?', '<p>How are you?</p>'),
+(1, 1, 'Hello, world!', '<p>Hello, world!</p>'),
+(1, 1, 'Hello, world!', '<p>Hello, world!</p>');
+UPDATE workspaces SET owner_id = 1 WHERE name = 'acme';
+INSERT INTO workspace_members(ws_id, user_id)
+SELECT
+  ws_id,
+  id
+FROM
+  users
+WHERE
+  id > 0;
+-- Ivena also works with foo
+INSERT INTO workspace_members(ws_id, user_id)
+  VALUES (2, 1);
+UPDATE
+  workspace_members
+SET
+  role = 'owner'
+WHERE
+  ws_id = 1
+  AND user_id = 1;
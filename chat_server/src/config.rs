@@ -1,6 +1,7 @@
 use std::{fs::File, path::PathBuf};
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use chat_core::{DecodingKey, EncodingKey};
 use serde::{Deserialize, Serialize};
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
@@ -10,17 +11,51 @@ pub struct AppConfig {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthConfig {
+    /// the active signing key
     pub sk: String,
     pub pk: String,
+    /// key id of the active key, defaults to the thumbprint of `pk`
+    #[serde(default)]
+    pub kid: Option<String>,
+    /// retired public keys, tokens signed with them are still accepted
+    #[serde(default)]
+    pub keys: Vec<PublicKeyConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PublicKeyConfig {
+    #[serde(default)]
+    pub kid: Option<String>,
+    pub pk: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub port: u16,
     pub db_url: String,
+    /// chats with more members don't share read receipts
+    #[serde(default = "default_receipts_max_members")]
+    pub receipts_max_members: usize,
     pub base_dir: PathBuf,
 }
 
+impl AuthConfig {
+    pub fn load_keys(&self) -> Result<(EncodingKey, DecodingKey)> {
+        let mut ek = EncodingKey::load(&self.sk).context("load sk failed")?;
+        if let Some(kid) = &self.kid {
+            ek = ek.with_kid(kid);
+        }
+        let mut dk = DecodingKey::default();
+        dk.add_pem(&self.pk, Some(ek.kid()))
+            .context("load pk failed")?;
+        for key in &self.keys {
+            dk.add_pem(&key.pk, key.kid.as_deref())
+                .with_context(|| format!("load pk {:?} failed", key.kid))?;
+        }
+        Ok((ek, dk))
+    }
+}
+
 impl AppConfig {
     pub fn load() -> Result<Self> {
         //read from ./app.yml or /etc/config/app.yml or env::var("CHAT_CONFIG")?
@@ -37,3 +72,7 @@ impl AppConfig {
         Ok(ret?)
     }
 }
+
+fn default_receipts_max_members() -> usize {
+    50
+}
@@ -39,12 +39,22 @@ pub enum AppError {
     IoError(#[from] std::io::Error),
     #[error("Unauthorized: {0}")]
     Unauthorized(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
     #[error("Update chat error: {0}")]
     UpdateChatError(String),
     #[error("{0}")]
     ChatFileError(String),
     #[error("create message error: {0}")]
     CreateMessageError(String),
+    #[error("update message error: {0}")]
+    UpdateMessageError(String),
+    #[error("invalid invite: {0}")]
+    InvalidInvite(String),
+    #[error("invalid search: {0}")]
+    InvalidSearch(String),
+    #[error("invalid reaction: {0}")]
+    InvalidReaction(String),
 }
 
 impl IntoResponse for AppError {
@@ -59,9 +69,14 @@ impl IntoResponse for AppError {
             Self::NotFound(_) => StatusCode::NOT_FOUND,
             Self::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
             Self::UpdateChatError(_) => StatusCode::BAD_REQUEST,
             Self::ChatFileError(_) => StatusCode::BAD_REQUEST,
             Self::CreateMessageError(_) => StatusCode::BAD_REQUEST,
+            Self::UpdateMessageError(_) => StatusCode::BAD_REQUEST,
+            Self::InvalidInvite(_) => StatusCode::FORBIDDEN,
+            Self::InvalidSearch(_) => StatusCode::BAD_REQUEST,
+            Self::InvalidReaction(_) => StatusCode::BAD_REQUEST,
         };
 
         (status, Json(json!({ "error": self.to_string() }))).into_response()
@@ -1,16 +1,30 @@
 use crate::{
     error::ErrorOutput,
-    models::user::{CreateUser, SigninUser},
+    models::{
+        session::{RefreshToken, Session},
+        user::{CreateUser, SigninUser},
+    },
     AppError, AppState,
 };
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_extra::{headers::UserAgent, TypedHeader};
+use chat_core::{Jwks, User, JWT_DURATION};
 use serde::{Deserialize, Serialize};
 use tracing::info;
 use utoipa::ToSchema;
 
 #[derive(Debug, Serialize, ToSchema, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct AuthOutput {
-    token: String,
+    pub(crate) token: String,
+    refresh_token: String,
+    /// seconds until the access token expires
+    expires_in: u64,
 }
 #[utoipa::path(
     post,
@@ -24,18 +38,18 @@ pub struct AuthOutput {
 /// - If the email already exists, it will return 409.
 /// - Otherwise, it will return 201 with a token.
 /// - If the workspace doesn't exist, it will create one.
+/// - Joining an existing workspace requires a valid `invite_code`, otherwise it will return 403.
 pub(crate) async fn signup_handler(
     State(state): State<AppState>,
+    user_agent: Option<TypedHeader<UserAgent>>,
     Json(input): Json<CreateUser>,
 ) -> Result<impl IntoResponse, AppError> {
     let user = state.create_user(&input).await?;
     info!("user created: {:?}", user.clone());
-    let token = state.ek.sign(user)?;
-
-    // let mut header = HeaderMap::new();
-    // header.insert("X-Token", HeaderValue::from_str(&token)?);
-    // Ok((StatusCode::CREATED, header))
-    let body = Json(AuthOutput { token });
+    let (session, refresh_token) = state
+        .create_session(user.id, user.ws_id, user_agent.as_ref().map(|v| v.as_str()))
+        .await?;
+    let body = Json(state.issue_token(user, session.id, refresh_token)?);
     Ok((StatusCode::CREATED, body))
 }
 #[utoipa::path(
@@ -47,13 +61,17 @@ pub(crate) async fn signup_handler(
 )]
 pub(crate) async fn signin_handler(
     State(state): State<AppState>,
+    user_agent: Option<TypedHeader<UserAgent>>,
     Json(input): Json<SigninUser>,
 ) -> Result<impl IntoResponse, AppError> {
     let user = state.verify_user(&input).await?;
     match user {
         Some(user) => {
-            let token = state.ek.sign(user)?;
-            Ok((StatusCode::OK, Json(AuthOutput { token })).into_response())
+            let (session, refresh_token) = state
+                .create_session(user.id, user.ws_id, user_agent.as_ref().map(|v| v.as_str()))
+                .await?;
+            let body = Json(state.issue_token(user, session.id, refresh_token)?);
+            Ok((StatusCode::OK, body).into_response())
         }
         None => {
             let body = Json(ErrorOutput::new("Invalid email or password"));
@@ -62,18 +80,143 @@ pub(crate) async fn signin_handler(
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/refresh",
+    responses(
+        (status = 200, description = "Token refreshed", body = AuthOutput),
+        (status = 401, description = "Invalid or expired refresh token", body = ErrorOutput),
+    )
+)]
+/// Exchange a refresh token for a new access token.
+///
+/// The refresh token is rotated, so the one in the request can't be used again.
+pub(crate) async fn refresh_handler(
+    State(state): State<AppState>,
+    Json(input): Json<RefreshToken>,
+) -> Result<impl IntoResponse, AppError> {
+    let (session, refresh_token) = state.refresh_session(&input.refresh_token).await?;
+    let mut user = state
+        .find_user_by_id(session.user_id)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("User no longer exists".to_string()))?;
+    // keep the workspace the session was switched to, the default workspace may have
+    // been switched on another device since
+    let ws_id = session.ws_id.unwrap_or(user.ws_id);
+    let ws = state
+        .find_member_workspace(ws_id as _, user.id as _)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("No longer a member of the workspace".to_string()))?;
+    user.ws_id = ws.id;
+    user.ws_name = ws.name;
+    Ok(Json(state.issue_token(user, session.id, refresh_token)?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/signout",
+    responses(
+        (status = 204, description = "Current session revoked"),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+/// Revoke the session of the current access token.
+pub(crate) async fn signout_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    if let Some(sid) = user.sid {
+        state.revoke_session(sid, user.id).await?;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/sessions",
+    responses(
+        (status = 200, description = "Active sessions of the current user", body = Vec<Session>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_sessions_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let sessions = state.fetch_sessions(user.id).await?;
+    Ok(Json(sessions))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/sessions/{id}",
+    params(
+        ("id" = i64, Path, description = "Session id")
+    ),
+    responses(
+        (status = 204, description = "Session revoked"),
+        (status = 404, description = "Session not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+/// Revoke one of the current user's sessions, e.g. a lost device.
+pub(crate) async fn revoke_session_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_session(id, user.id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    responses(
+        (status = 200, description = "Public keys accepted for token verification", body = Jwks),
+    )
+)]
+/// Publish the verification keys so other services can follow key rotation.
+pub(crate) async fn jwks_handler(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.dk.to_jwks())
+}
+
+impl AppState {
+    pub(crate) fn issue_token(
+        &self,
+        mut user: User,
+        sid: i64,
+        refresh_token: String,
+    ) -> Result<AuthOutput, AppError> {
+        user.sid = Some(sid);
+        let token = self.ek.sign(user)?;
+        Ok(AuthOutput {
+            token,
+            refresh_token,
+            expires_in: JWT_DURATION,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::error::ErrorOutput;
     use anyhow::Result;
+    use chat_core::middlewares::TokenVerify;
     use http_body_util::BodyExt;
 
     #[tokio::test]
     async fn signup_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let input = CreateUser::new("family", "ZhenyuHuang", "test@test.org", "123456");
-        let ret = signup_handler(State(state), Json(input))
+        let ret = signup_handler(State(state), None, Json(input))
             .await?
             .into_response();
         assert_eq!(ret.status(), StatusCode::CREATED);
@@ -83,12 +226,30 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn jwks_should_verify_issued_token() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let ret = jwks_handler(State(state.clone())).await.into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let jwks: Jwks = serde_json::from_slice(&body)?;
+        assert_eq!(jwks.keys.len(), 1);
+        assert_eq!(jwks.keys[0].kid, state.ek.kid());
+
+        let mut dk = chat_core::DecodingKey::default();
+        dk.add_jwks(&jwks)?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let token = state.ek.sign(user.clone())?;
+        assert_eq!(dk.verify(&token)?, user);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn signup_duplicate_user_should_409() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let input = CreateUser::new("acme", "Ivena", "test2@acme.org", "123456");
 
-        let ret = signup_handler(State(state), Json(input))
+        let ret = signup_handler(State(state), None, Json(input))
             .await
             .into_response();
         assert_eq!(ret.status(), StatusCode::CONFLICT);
@@ -105,14 +266,88 @@ mod tests {
         let email = "test2@acme.org";
         let password = "123456";
         let input = SigninUser::new(email, password);
-        let ret = signin_handler(State(state), Json(input))
+        let ret = signin_handler(State(state), None, Json(input))
             .await?
             .into_response();
         assert_eq!(ret.status(), StatusCode::OK);
         let body = ret.into_body().collect().await?.to_bytes();
         let ret: AuthOutput = serde_json::from_slice(&body)?;
         assert_ne!(ret.token, "");
+        assert_ne!(ret.refresh_token, "");
+
+        Ok(())
+    }
 
+    #[tokio::test]
+    async fn refresh_and_signout_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = SigninUser::new("test2@acme.org", "123456");
+        let ret = signin_handler(State(state.clone()), None, Json(input))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let auth: AuthOutput = serde_json::from_slice(&body)?;
+        let user = state.dk.verify(&auth.token)?;
+
+        let input = RefreshToken {
+            refresh_token: auth.refresh_token.clone(),
+        };
+        let ret = refresh_handler(State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let refreshed: AuthOutput = serde_json::from_slice(&body)?;
+        assert_ne!(refreshed.refresh_token, auth.refresh_token);
+        assert_eq!(state.dk.verify(&refreshed.token)?.sid, user.sid);
+
+        let ret = signout_handler(Extension(user), State(state.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+
+        // both the access token and the refresh token are rejected after signout
+        assert!(state.verify(&refreshed.token).await.is_err());
+        let input = RefreshToken {
+            refresh_token: refreshed.refresh_token,
+        };
+        let ret = refresh_handler(State(state), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refresh_should_keep_session_workspace() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let (_, refresh_token) = state.create_session(user.id, user.ws_id, None).await?;
+        // another device switched to foo
+        state.set_default_workspace(user.id as _, 2).await?;
+
+        let input = RefreshToken {
+            refresh_token: refresh_token.clone(),
+        };
+        let ret = refresh_handler(State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let auth: AuthOutput = serde_json::from_slice(&body)?;
+        let refreshed = state.dk.verify(&auth.token)?;
+        assert_eq!(refreshed.ws_id, 1);
+        assert_eq!(refreshed.ws_name, "acme");
+
+        sqlx::query("DELETE FROM workspace_members WHERE ws_id = 1 AND user_id = 1")
+            .execute(&state.pg_pool)
+            .await?;
+        let input = RefreshToken {
+            refresh_token: auth.refresh_token,
+        };
+        let ret = refresh_handler(State(state), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::UNAUTHORIZED);
         Ok(())
     }
     #[tokio::test]
@@ -121,7 +356,7 @@ mod tests {
         let email = "tchen1@acme.org";
         let password = "123456";
         let input = SigninUser::new(email, password);
-        let ret = signin_handler(State(state), Json(input))
+        let ret = signin_handler(State(state), None, Json(input))
             .await
             .into_response();
         assert_eq!(ret.status(), StatusCode::FORBIDDEN);
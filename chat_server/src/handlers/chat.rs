@@ -1,22 +1,32 @@
 use crate::{
     error::ErrorOutput,
-    models::chat::{CreateChat, UpdateChat},
+    models::{
+        chat::{ChannelSummary, ChatListItem, CreateChat, ListChats, UpdateChat},
+        permission::Action,
+        pin::{ChatDetail, PinnedMessage},
+        read::{ListReceipts, MarkRead},
+        ChatFile,
+    },
     AppError, AppState,
 };
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
     Extension, Json,
 };
-use chat_core::{Chat, User};
-use sqlx::{Postgres, Transaction};
-use tracing::info;
+use chat_core::{Chat, ReadMarker, User};
+use std::str::FromStr;
+use tokio::fs;
+use tracing::{info, warn};
 #[utoipa::path(
     get,
     path = "/api/chats",
+    params(
+        ListChats
+    ),
     responses(
-        (status = 200, description = "List of chats", body = Vec<Chat>),
+        (status = 200, description = "List of chats", body = Vec<ChatListItem>),
     ),
     security(
         ("token" = [])
@@ -25,8 +35,11 @@ use tracing::info;
 pub(crate) async fn list_chat_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
+    Query(input): Query<ListChats>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = state.fetch_chats(user.id as _, user.ws_id as _).await?;
+    let chat = state
+        .fetch_chats(user.id as _, user.ws_id as _, input)
+        .await?;
     // let chat = state.fetch_chats(user.ws_id as _).await?;
 
     Ok((StatusCode::OK, Json(chat)))
@@ -52,6 +65,37 @@ pub(crate) async fn create_chat_handler(
         .await?;
     Ok((StatusCode::CREATED, Json(chat)))
 }
+#[utoipa::path(
+    post,
+    path = "/api/dm/{user_id}",
+    params(
+        ("user_id" = u64, Path, description = "User to message")
+    ),
+    responses(
+        (status = 200, description = "Existing direct message chat", body = Chat),
+        (status = 201, description = "Direct message chat created", body = Chat),
+        (status = 404, description = "User not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn dm_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(other_id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let (chat, created) = state
+        .get_or_create_dm(user.ws_id as _, user.id as _, other_id)
+        .await?;
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, Json(chat)))
+}
+
 #[utoipa::path(
     get,
     path = "/api/chats/{id}",
@@ -59,7 +103,7 @@ pub(crate) async fn create_chat_handler(
         ("id" = u64, Path, description = "Chat id")
     ),
     responses(
-        (status = 200, description = "Chat found", body = Chat),
+        (status = 200, description = "Chat found, with its pinned messages", body = ChatDetail),
         (status = 404, description = "Chat not found", body = ErrorOutput),
     ),
     security(
@@ -72,59 +116,302 @@ pub(crate) async fn get_chat_handler(
 ) -> Result<impl IntoResponse, AppError> {
     let chat = state.get_chat_by_id(id as _).await?;
     match chat {
-        Some(chat) => Ok(Json(chat)),
+        Some(chat) => {
+            let pins = state.list_pins(id).await?;
+            Ok(Json(ChatDetail { chat, pins }))
+        }
         None => Err(AppError::NotFound(format!("chat id {id}"))),
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/pins",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Pinned messages, oldest pin first", body = Vec<PinnedMessage>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_pins_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let pins = state.list_pins(id).await?;
+    Ok(Json(pins))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/pins/{mid}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("mid" = u64, Path, description = "Message id")
+    ),
+    responses(
+        (status = 200, description = "Pinned messages of the chat", body = Vec<PinnedMessage>),
+        (status = 400, description = "Too many pins or the chat is archived", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn pin_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let pins = state.pin_message(id, mid, user.id as _).await?;
+    Ok(Json(pins))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/pins/{mid}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("mid" = u64, Path, description = "Message id")
+    ),
+    responses(
+        (status = 200, description = "Pinned messages of the chat", body = Vec<PinnedMessage>),
+        (status = 404, description = "Message is not pinned", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn unpin_message_handler(
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let pins = state.unpin_message(id, mid).await?;
+    Ok(Json(pins))
+}
+
 pub(crate) async fn update_chat_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
     Path(id): Path<u64>,
     Json(input): Json<UpdateChat>,
 ) -> Result<impl IntoResponse, AppError> {
-    let mut chat = state.fetch_and_verify_chat(id, &user).await?;
+    let mut chat = state
+        .fetch_and_verify_chat(id, &user, Action::UpdateChat)
+        .await?;
     state.apply_updates(&mut chat, input).await?;
     Ok((StatusCode::OK, Json(chat)))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 204, description = "Chat and its history deleted"),
+        (status = 403, description = "Not a workspace admin", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+/// Purge a chat with all of its messages and files. Use archive to hide a chat instead.
 pub(crate) async fn delete_chat_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
     Path(id): Path<u64>,
 ) -> Result<impl IntoResponse, AppError> {
-    let mut tx: Transaction<'_, Postgres> = state.pg_pool.begin().await?;
+    let chat = state
+        .fetch_and_verify_chat(id, &user, Action::PurgeChat)
+        .await?;
+    let files = state.purge_chat(chat.id).await?;
 
-    let chat = sqlx::query_as::<_, Chat>(
-        r#"
-            SELECT id, ws_id, name, chat_type, members, created_at
-            FROM chats
-            WHERE id = $1
-            "#,
-    )
-    .bind(id as i64)
-    .fetch_optional(tx.as_mut())
-    .await?
-    .ok_or_else(|| AppError::NotFound(format!("Chat id {}", id)))?;
-
-    if chat.ws_id != user.ws_id {
-        return Err(AppError::Unauthorized(
-            "You do not have permission to delete this chat.".into(),
-        ));
+    // the messages are gone already, a file left behind is only wasted space
+    let base_dir = &state.config.server.base_dir;
+    for s in files {
+        let Ok(file) = ChatFile::from_str(&s) else {
+            continue;
+        };
+        if let Err(e) = fs::remove_file(file.path(base_dir)).await {
+            warn!("Failed to remove file {}: {}", s, e);
+        }
     }
 
-    sqlx::query(
-        r#"
-        DELETE FROM chats
-        WHERE id = $1
-        "#,
+    info!("Chat {} purged by user {}", id, user.id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/archive",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Chat archived", body = Chat),
+        (status = 403, description = "Not a chat or workspace admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
     )
-    .bind(chat.id)
-    .execute(tx.as_mut())
-    .await?;
+)]
+pub(crate) async fn archive_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut chat = state
+        .fetch_and_verify_chat(id, &user, Action::ArchiveChat)
+        .await?;
+    state.set_chat_archived(&mut chat, true).await?;
+    Ok(Json(chat))
+}
 
-    tx.commit().await?;
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/unarchive",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Chat restored", body = Chat),
+        (status = 403, description = "Not a chat or workspace admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn unarchive_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut chat = state
+        .fetch_and_verify_chat(id, &user, Action::ArchiveChat)
+        .await?;
+    state.set_chat_archived(&mut chat, false).await?;
+    Ok(Json(chat))
+}
 
-    info!("Chat {} deleted successfully by user {}", id, user.ws_id);
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/read",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    request_body = MarkRead,
+    responses(
+        (status = 200, description = "Read marker of the user", body = ReadMarker),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn mark_read_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<MarkRead>,
+) -> Result<impl IntoResponse, AppError> {
+    let marker = state.mark_read(input, id, user.id as _).await?;
+    Ok(Json(marker))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/receipts",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ListReceipts
+    ),
+    responses(
+        (status = 200, description = "Read markers of the other members", body = Vec<ReadMarker>),
+        (status = 403, description = "Read receipts are disabled for the chat", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_receipts_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Query(input): Query<ListReceipts>,
+) -> Result<impl IntoResponse, AppError> {
+    let receipts = state.list_receipts(input, id, user.id as _).await?;
+    Ok(Json(receipts))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/channels",
+    responses(
+        (status = 200, description = "Public channels of the workspace", body = Vec<ChannelSummary>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_channels_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let channels = state
+        .fetch_public_channels(user.ws_id as _, user.id as _)
+        .await?;
+    Ok(Json(channels))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/join",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Joined the channel", body = Chat),
+        (status = 403, description = "Not a public channel", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn join_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.join_chat(id, &user).await?;
+    Ok(Json(chat))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/leave",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 204, description = "Left the chat"),
+        (status = 404, description = "Not a member of the chat", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn leave_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.leave_chat(id, user.id as _).await?;
     Ok(StatusCode::NO_CONTENT)
 }
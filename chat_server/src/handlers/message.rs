@@ -4,14 +4,17 @@ use axum::{
     response::IntoResponse,
     Extension, Json,
 };
-use chat_core::{Message, User};
+use chat_core::{Message, ReactionCount, User};
 use tokio::fs;
 use tracing::{info, warn};
 
 use crate::{
     error::ErrorOutput,
     models::{
-        message::{CreateMessage, ListMessages},
+        mention::ListMentions,
+        message::{CreateMessage, ListMessages, MessageEdit, MessagePage, Thread, UpdateMessage},
+        scheduled::{CreateScheduledMessage, ScheduledMessage, UpdateScheduledMessage},
+        search::{SearchMessages, SearchResult},
         ChatFile,
     },
     AppError, AppState,
@@ -24,7 +27,8 @@ use crate::{
         ("id" = u64, Path, description = "Chat id")
     ),
     responses(
-        (status = 200, description = "List of messages", body = Message),
+        (status = 201, description = "Message created", body = Message),
+        (status = 200, description = "Message already sent with the same client id", body = Message),
         (status = 400, description = "Invalid input", body = ErrorOutput),
     ),
     security(
@@ -37,8 +41,13 @@ pub(crate) async fn send_message_handler(
     Path(id): Path<u64>,
     Json(input): Json<CreateMessage>,
 ) -> Result<impl IntoResponse, AppError> {
-    let msg = state.create_message(input, id, user.id as _).await?;
-    Ok((StatusCode::CREATED, Json(msg)))
+    let (msg, created) = state.send_message(input, id, user.id as _).await?;
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, Json(msg)))
 }
 #[utoipa::path(
     get,
@@ -48,7 +57,7 @@ pub(crate) async fn send_message_handler(
         ListMessages
     ),
     responses(
-        (status = 200, description = "List of messages", body = Vec<Message>),
+        (status = 200, description = "A page of messages", body = MessagePage),
         (status = 400, description = "Invalid input", body = ErrorOutput),
     ),
     security(
@@ -63,6 +72,303 @@ pub(crate) async fn list_messages_handler(
     let messages = state.list_messages(input, id).await?;
     Ok(Json(messages))
 }
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{id}/messages/{mid}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("mid" = u64, Path, description = "Message id")
+    ),
+    request_body = UpdateMessage,
+    responses(
+        (status = 200, description = "Message updated", body = Message),
+        (status = 403, description = "Not the sender of the message", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+    Json(input): Json<UpdateMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let msg = state.update_message(input, id, mid, user.id as _).await?;
+    Ok(Json(msg))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/messages/{mid}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("mid" = u64, Path, description = "Message id")
+    ),
+    responses(
+        (status = 204, description = "Message deleted"),
+        (status = 403, description = "Not the sender or an admin", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn delete_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_message(id, mid, &user).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/messages/{mid}/edits",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("mid" = u64, Path, description = "Message id")
+    ),
+    responses(
+        (status = 200, description = "Previous versions of the message", body = Vec<MessageEdit>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_message_edits_handler(
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let edits = state.list_message_edits(id, mid).await?;
+    Ok(Json(edits))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/messages/{mid}/reactions/{emoji}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("mid" = u64, Path, description = "Message id"),
+        ("emoji" = String, Path, description = "Emoji of the reaction")
+    ),
+    responses(
+        (status = 200, description = "Reactions of the message", body = Vec<ReactionCount>),
+        (status = 400, description = "Invalid emoji", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn add_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid, emoji)): Path<(u64, u64, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let reactions = state.add_reaction(id, mid, user.id as _, &emoji).await?;
+    Ok(Json(reactions))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/messages/{mid}/reactions/{emoji}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("mid" = u64, Path, description = "Message id"),
+        ("emoji" = String, Path, description = "Emoji of the reaction")
+    ),
+    responses(
+        (status = 200, description = "Reactions of the message", body = Vec<ReactionCount>),
+        (status = 400, description = "Invalid emoji", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn remove_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid, emoji)): Path<(u64, u64, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let reactions = state.remove_reaction(id, mid, user.id as _, &emoji).await?;
+    Ok(Json(reactions))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/messages/{mid}/thread",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("mid" = u64, Path, description = "Message id"),
+        ListMessages
+    ),
+    responses(
+        (status = 200, description = "The message with its replies", body = Thread),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_thread_handler(
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+    Query(input): Query<ListMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let thread = state.fetch_thread(input, id, mid).await?;
+    Ok(Json(thread))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/search",
+    params(
+        SearchMessages
+    ),
+    responses(
+        (status = 200, description = "Matching messages, newest first", body = Vec<SearchResult>),
+        (status = 400, description = "Invalid search query", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn search_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<SearchMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let results = state
+        .search_messages(input, user.id as _, user.ws_id as _)
+        .await?;
+    Ok(Json(results))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/mentions",
+    params(
+        ListMentions
+    ),
+    responses(
+        (status = 200, description = "Messages mentioning the user, newest first", body = Vec<Message>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_mentions_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListMentions>,
+) -> Result<impl IntoResponse, AppError> {
+    let messages = state
+        .list_mentions(input, user.id as _, user.ws_id as _)
+        .await?;
+    Ok(Json(messages))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/scheduled",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    request_body = CreateScheduledMessage,
+    responses(
+        (status = 201, description = "Message scheduled", body = ScheduledMessage),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_scheduled_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<CreateScheduledMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let message = state
+        .create_scheduled_message(input, id, user.id as _)
+        .await?;
+    Ok((StatusCode::CREATED, Json(message)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/scheduled",
+    responses(
+        (status = 200, description = "Scheduled messages not sent yet", body = Vec<ScheduledMessage>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_scheduled_messages_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let messages = state
+        .list_scheduled_messages(user.id as _, user.ws_id as _)
+        .await?;
+    Ok(Json(messages))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/scheduled/{id}",
+    params(
+        ("id" = u64, Path, description = "Scheduled message id")
+    ),
+    request_body = UpdateScheduledMessage,
+    responses(
+        (status = 200, description = "Scheduled message updated", body = ScheduledMessage),
+        (status = 404, description = "Scheduled message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_scheduled_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<UpdateScheduledMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let message = state
+        .update_scheduled_message(input, id, user.id as _)
+        .await?;
+    Ok(Json(message))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/scheduled/{id}",
+    params(
+        ("id" = u64, Path, description = "Scheduled message id")
+    ),
+    responses(
+        (status = 204, description = "Scheduled message canceled"),
+        (status = 404, description = "Scheduled message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn cancel_scheduled_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.cancel_scheduled_message(id, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 pub(crate) async fn file_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
@@ -1,7 +1,23 @@
-use axum::{extract::State, response::IntoResponse, Extension, Json};
-use chat_core::{ChatUser, User};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_extra::{headers::UserAgent, TypedHeader};
+use chat_core::{ChatUser, User, Workspace};
 
-use crate::{AppError, AppState};
+use crate::{
+    error::ErrorOutput,
+    handlers::AuthOutput,
+    models::{
+        user::CreateUser,
+        workspace::{
+            AcceptInvite, CreateInvite, UpdateMemberRole, WorkspaceInvite, WorkspaceMember,
+        },
+    },
+    AppError, AppState,
+};
 #[utoipa::path(
     get,
     path = "/api/users",
@@ -20,3 +36,260 @@ pub(crate) async fn list_chat_users_handler(
     let users = state.fetch_all_chat_users(user.ws_id as _).await?;
     Ok(Json(users))
 }
+
+#[utoipa::path(
+    post,
+    path = "/api/invites",
+    responses(
+        (status = 201, description = "Invite created", body = WorkspaceInvite),
+        (status = 403, description = "Not a workspace admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateInvite>,
+) -> Result<impl IntoResponse, AppError> {
+    let invite = state
+        .create_invite(input, user.ws_id as _, user.id as _)
+        .await?;
+    Ok((StatusCode::CREATED, Json(invite)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/invites",
+    responses(
+        (status = 200, description = "Invites of the workspace", body = Vec<WorkspaceInvite>),
+        (status = 403, description = "Not a workspace admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_invites_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let invites = state.fetch_invites(user.ws_id as _, user.id as _).await?;
+    Ok(Json(invites))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/invites/{id}",
+    params(
+        ("id" = u64, Path, description = "Invite id")
+    ),
+    responses(
+        (status = 204, description = "Invite revoked"),
+        (status = 404, description = "Invite not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn revoke_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .revoke_invite(id, user.ws_id as _, user.id as _)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/invites/{code}/accept",
+    params(
+        ("code" = String, Path, description = "Invite code")
+    ),
+    responses(
+        (status = 201, description = "User created in the invited workspace", body = AuthOutput),
+        (status = 403, description = "Invite is invalid or has expired", body = ErrorOutput),
+    )
+)]
+/// Sign up into the workspace of the invite.
+pub(crate) async fn accept_invite_handler(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    user_agent: Option<TypedHeader<UserAgent>>,
+    Json(input): Json<AcceptInvite>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state.find_workspace_by_invite(&code).await?;
+    let input = CreateUser {
+        username: input.username,
+        email: input.email,
+        workspace: ws.name,
+        password: input.password,
+        invite_code: Some(code),
+    };
+    let user = state.create_user(&input).await?;
+    let (session, refresh_token) = state
+        .create_session(user.id, user.ws_id, user_agent.as_ref().map(|v| v.as_str()))
+        .await?;
+    let body = Json(state.issue_token(user, session.id, refresh_token)?);
+    Ok((StatusCode::CREATED, body))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/invites/{code}/join",
+    params(
+        ("code" = String, Path, description = "Invite code")
+    ),
+    responses(
+        (status = 200, description = "Joined the invited workspace", body = Workspace),
+        (status = 403, description = "Invite is invalid or has expired", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+/// Join the workspace of the invite with the current account.
+pub(crate) async fn join_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state.join_workspace(&code, user.id as _).await?;
+    Ok(Json(ws))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspaces",
+    responses(
+        (status = 200, description = "Workspaces of the current user", body = Vec<Workspace>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_workspaces_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let workspaces = state.fetch_workspaces(user.id as _).await?;
+    Ok(Json(workspaces))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspaces/{id}/switch",
+    params(
+        ("id" = u64, Path, description = "Workspace id")
+    ),
+    responses(
+        (status = 200, description = "Token scoped to the workspace", body = AuthOutput),
+        (status = 404, description = "Not a member of the workspace", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+/// Issue a new token pair scoped to another workspace of the current user.
+pub(crate) async fn switch_workspace_handler(
+    Extension(mut user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .find_member_workspace(id, user.id as _)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("workspace id {id}")))?;
+    let sid = user
+        .sid
+        .ok_or_else(|| AppError::Unauthorized("token has no session".to_string()))?;
+    let (session, refresh_token) = state.switch_session_workspace(sid, user.id, ws.id).await?;
+    state.set_default_workspace(user.id as _, id).await?;
+    user.ws_id = ws.id;
+    user.ws_name = ws.name;
+    Ok(Json(state.issue_token(user, session.id, refresh_token)?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/members",
+    responses(
+        (status = 200, description = "Members of the current workspace", body = Vec<WorkspaceMember>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_members_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let members = state.fetch_workspace_members(user.ws_id as _).await?;
+    Ok(Json(members))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/members/{id}",
+    params(
+        ("id" = u64, Path, description = "User id of the member")
+    ),
+    request_body = UpdateMemberRole,
+    responses(
+        (status = 200, description = "Role updated", body = WorkspaceMember),
+        (status = 403, description = "Not allowed to change the role", body = ErrorOutput),
+        (status = 404, description = "Member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_member_role_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<UpdateMemberRole>,
+) -> Result<impl IntoResponse, AppError> {
+    let member = state
+        .update_member_role(user.ws_id as _, user.id as _, id, input.role)
+        .await?;
+    Ok(Json(member))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use chat_core::middlewares::TokenVerify;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn switch_workspace_should_scope_token() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut user = state.find_user_by_id(1).await?.expect("user should exist");
+        let (session, _) = state.create_session(user.id, user.ws_id, None).await?;
+        user.sid = Some(session.id);
+
+        let ret = switch_workspace_handler(Extension(user.clone()), State(state.clone()), Path(3))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NOT_FOUND);
+
+        let ret = switch_workspace_handler(Extension(user), State(state.clone()), Path(2))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let auth: AuthOutput = serde_json::from_slice(&body)?;
+        let user = state.verify(&auth.token).await?;
+        assert_eq!(user.ws_id, 2);
+        assert_eq!(state.fetch_all_chat_users(user.ws_id as _).await?.len(), 1);
+        // next signin lands in the same workspace
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        assert_eq!(user.ws_id, 2);
+        Ok(())
+    }
+}
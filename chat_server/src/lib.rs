@@ -8,7 +8,7 @@ use anyhow::Context;
 use axum::{
     http::Method,
     middleware::from_fn_with_state,
-    routing::{get, post},
+    routing::{delete, get, patch, post, put},
     Router,
 };
 use chat_core::{
@@ -19,6 +19,7 @@ pub use config::AppConfig;
 pub use error::AppError;
 use handlers::*;
 use middlewares::chat::verify_chat;
+pub use models::{expiry::setup_reaper, scheduled::setup_scheduler};
 
 use openapi::OpenApiRouter;
 use tokio::fs;
@@ -39,15 +40,36 @@ pub struct AppStateInner {
 }
 pub async fn get_router(state: AppState) -> Result<Router, AppError> {
     let chat = Router::new()
+        .route("/:id", get(get_chat_handler).post(send_message_handler))
+        .route("/:id/messages", get(list_messages_handler))
+        .route("/:id/read", post(mark_read_handler))
+        .route("/:id/scheduled", post(create_scheduled_message_handler))
+        .route("/:id/receipts", get(list_receipts_handler))
+        .route("/:id/pins", get(list_pins_handler))
         .route(
-            "/:id",
-            get(get_chat_handler)
-                .patch(update_chat_handler)
-                .delete(delete_chat_handler)
-                .post(send_message_handler),
+            "/:id/pins/:mid",
+            put(pin_message_handler).delete(unpin_message_handler),
+        )
+        .route(
+            "/:id/messages/:mid",
+            patch(update_message_handler).delete(delete_message_handler),
+        )
+        .route("/:id/messages/:mid/edits", get(list_message_edits_handler))
+        .route("/:id/messages/:mid/thread", get(get_thread_handler))
+        .route(
+            "/:id/messages/:mid/reactions/:emoji",
+            put(add_reaction_handler).delete(remove_reaction_handler),
         )
-        .route("/:id/messages", get(list_messages_handler))
         .layer(from_fn_with_state(state.clone(), verify_chat))
+        // workspace admins can manage chats they are not a member of
+        .route(
+            "/:id",
+            patch(update_chat_handler).delete(delete_chat_handler),
+        )
+        .route("/:id/archive", post(archive_chat_handler))
+        .route("/:id/unarchive", post(unarchive_chat_handler))
+        .route("/:id/join", post(join_chat_handler))
+        .route("/:id/leave", post(leave_chat_handler))
         .route("/", get(list_chat_handler).post(create_chat_handler));
 
     let cors = CorsLayer::new()
@@ -64,17 +86,42 @@ pub async fn get_router(state: AppState) -> Result<Router, AppError> {
     let api = Router::new()
         .route("/users", get(list_chat_users_handler))
         .nest("/chats", chat)
+        .route("/channels", get(list_channels_handler))
+        .route("/dm/:user_id", post(dm_handler))
+        .route("/search", get(search_handler))
+        .route("/mentions", get(list_mentions_handler))
+        .route("/scheduled", get(list_scheduled_messages_handler))
+        .route(
+            "/scheduled/:id",
+            patch(update_scheduled_message_handler).delete(cancel_scheduled_message_handler),
+        )
         .route("/upload", post(upload_handler))
         .route("/files/:ws_id/*path", get(file_handler))
+        .route("/signout", post(signout_handler))
+        .route("/sessions", get(list_sessions_handler))
+        .route("/sessions/:id", delete(revoke_session_handler))
+        .route(
+            "/invites",
+            get(list_invites_handler).post(create_invite_handler),
+        )
+        .route("/invites/:id", delete(revoke_invite_handler))
+        .route("/invites/:code/join", post(join_invite_handler))
+        .route("/members", get(list_members_handler))
+        .route("/members/:id", patch(update_member_role_handler))
+        .route("/workspaces", get(list_workspaces_handler))
+        .route("/workspaces/:id/switch", post(switch_workspace_handler))
         .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
         // routes doesn't need token verification
         .route("/signin", post(signin_handler))
         .route("/signup", post(signup_handler))
+        .route("/refresh", post(refresh_handler))
+        .route("/invites/:code/accept", post(accept_invite_handler))
         .layer(cors);
 
     let app = Router::new()
         .openapi()
         .route("/", get(index_handler))
+        .route("/.well-known/jwks.json", get(jwks_handler))
         .nest("/api", api)
         .with_state(state);
     Ok(set_layer(app))
@@ -90,8 +137,18 @@ impl Deref for AppState {
 impl TokenVerify for AppState {
     type Error = AppError;
 
-    fn verify(&self, token: &str) -> Result<User, Self::Error> {
-        Ok(self.dk.verify(token)?)
+    async fn verify(&self, token: &str) -> Result<User, Self::Error> {
+        let user = self.dk.verify(token)?;
+        // tokens are bound to a session so they can be revoked before they expire
+        let Some(sid) = user.sid else {
+            return Err(AppError::Unauthorized("token has no session".to_string()));
+        };
+        if !self.is_session_active(sid, user.id, user.ws_id).await? {
+            return Err(AppError::Unauthorized(
+                "session has been revoked or user left the workspace".to_string(),
+            ));
+        }
+        Ok(user)
     }
 }
 
@@ -100,8 +157,7 @@ impl AppState {
         fs::create_dir_all(&config.server.base_dir)
             .await
             .context("create base_dir failed")?;
-        let dk = DecodingKey::load(&config.auth.pk).context("load pk failed")?;
-        let ek = EncodingKey::load(&config.auth.sk).context("load sk failed")?;
+        let (ek, dk) = config.auth.load_keys()?;
         let pool = sqlx::PgPool::connect(&config.server.db_url)
             .await
             .context("connect to db failed")?;
@@ -134,8 +190,7 @@ mod test_util {
     impl AppState {
         pub async fn new_for_test() -> Result<(TestPg, Self), AppError> {
             let config = AppConfig::load()?;
-            let dk = DecodingKey::load(&config.auth.pk).context("load pk failed")?;
-            let ek = EncodingKey::load(&config.auth.sk).context("load sk failed")?;
+            let (ek, dk) = config.auth.load_keys()?;
             let post = config.server.db_url.rfind('/').expect("invalid db_url");
             let server_url = &config.server.db_url[..post];
             let (tdb, pool) = get_test_pool(Some(server_url)).await;
@@ -2,7 +2,7 @@ use tokio::net::TcpListener;
 
 use anyhow::Result;
 
-use chat_server::{get_router, AppConfig, AppState};
+use chat_server::{get_router, setup_reaper, setup_scheduler, AppConfig, AppState};
 use tracing::{info, level_filters::LevelFilter};
 use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
 #[tokio::main]
@@ -14,8 +14,10 @@ async fn main() -> Result<()> {
     let addr = format!("0.0.0.0:{}", config.server.port);
 
     let state = AppState::try_new(config).await?;
-    let app = get_router(state).await?;
+    let app = get_router(state.clone()).await?;
     let listener = TcpListener::bind(&addr).await?;
+    setup_scheduler(state.clone());
+    setup_reaper(state);
 
     info!("Listening on: {}", addr);
 
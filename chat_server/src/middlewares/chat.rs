@@ -4,14 +4,19 @@ use axum::{
     response::{IntoResponse as _, Response},
 };
 use chat_core::User;
+use std::collections::HashMap;
 
 use crate::{AppError, AppState};
 #[allow(dead_code)]
 pub async fn verify_chat(State(state): State<AppState>, req: Request, next: Next) -> Response {
     let (mut parts, body) = req.into_parts();
-    let Path(chat_id) = Path::<u64>::from_request_parts(&mut parts, &state)
+    // routes below the chat can have more path params, e.g. /:id/messages/:mid
+    let Path(params) = Path::<HashMap<String, String>>::from_request_parts(&mut parts, &state)
         .await
         .unwrap();
+    let Some(chat_id) = params.get("id").and_then(|v| v.parse::<u64>().ok()) else {
+        return AppError::NotFound("chat id".to_string()).into_response();
+    };
     let user = parts.extensions.get::<User>().unwrap();
     if !state.is_chat_member(chat_id, user.id as _).await.unwrap() {
         let err = AppError::CreateMessageError(format!(
@@ -42,7 +47,9 @@ mod tests {
     #[tokio::test]
     async fn verify_chat_middleware_should_work() -> anyhow::Result<()> {
         let (_tdb, state) = crate::AppState::new_for_test().await.unwrap();
-        let user = state.find_user_by_id(1).await?.expect("user not found");
+        let mut user = state.find_user_by_id(1).await?.expect("user not found");
+        let (session, _) = state.create_session(user.id, user.ws_id, None).await?;
+        user.sid = Some(session.id);
         let token = state.ek.sign(user.clone()).unwrap();
         let app = Router::new()
             .route("/chat/:id/messages", get(handler))
@@ -1,9 +1,16 @@
 use chat_core::{Chat, ChatType, User};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
-use crate::{AppError, AppState};
+use crate::{
+    models::{message::MAX_MESSAGE_TTL_SECS, permission::Action},
+    AppError, AppState,
+};
+
+/// characters of the last message shown in the chat list
+const PREVIEW_LENGTH: i32 = 100;
 
 #[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
 pub struct CreateChat {
@@ -12,11 +19,67 @@ pub struct CreateChat {
     pub public: bool,
 }
 
+#[derive(Debug, Clone, Default, Serialize, IntoParams, ToSchema, Deserialize)]
+pub struct ListChats {
+    /// include archived chats
+    #[serde(default)]
+    pub archived: bool,
+    /// the last chat of the previous page
+    #[serde(default)]
+    pub last_id: Option<u64>,
+    /// page size, 100 at most
+    #[serde(default)]
+    pub limit: u64,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct UpdateChat {
     pub name: Option<String>,
     pub members: Option<Vec<i64>>,
     pub public: Option<bool>,
+    /// default time to live of new messages, 0 turns it off
+    #[serde(default, alias = "messageTtlSecs")]
+    pub message_ttl_secs: Option<u64>,
+}
+
+/// A chat in the chat list of the user, with its unread badges.
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatListItem {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub chat: Chat,
+    pub last_read_message_id: Option<i64>,
+    pub unread_count: i64,
+    /// unread messages mentioning the user
+    pub mention_count: i64,
+    pub last_message_at: Option<DateTime<Utc>>,
+    #[sqlx(json)]
+    pub last_message: Option<MessagePreview>,
+}
+
+/// The start of the last message of a chat, shown in the chat list.
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MessagePreview {
+    pub id: i64,
+    pub sender_id: i64,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// A public channel as listed in the channel directory.
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelSummary {
+    pub id: i64,
+    pub ws_id: i64,
+    pub name: String,
+    pub member_count: i64,
+    /// whether the current user already joined the channel
+    pub joined: bool,
+    pub created_at: DateTime<Utc>,
 }
 
 #[allow(dead_code)]
@@ -52,7 +115,9 @@ impl AppState {
                 "Group chat with more than 8 members must have a name".to_string(),
             ));
         }
-        let users = self.fetch_chat_user_by_ids(&input.members).await?;
+        let users = self
+            .fetch_chat_user_by_ids(ws_id as _, &input.members)
+            .await?;
         if users.len() != len {
             return Err(AppError::CreateChatError(
                 "Some members do not exist".to_string(),
@@ -69,31 +134,174 @@ impl AppState {
                 }
             }
         };
-        let chat = sqlx::query_as(
+        if matches!(chat_type, ChatType::Single) {
+            let other_id = input
+                .members
+                .iter()
+                .find(|&&id| id != user_id as i64)
+                .copied()
+                .unwrap_or_default();
+            let (chat, _) = self.get_or_create_dm(ws_id, user_id, other_id as _).await?;
+            return Ok(chat);
+        }
+        if matches!(
+            chat_type,
+            ChatType::PublicChannel | ChatType::PrivateChannel
+        ) {
+            self.authorize(ws_id, user_id, Action::CreateChannel)
+                .await?;
+        }
+        let mut tx = self.pg_pool.begin().await?;
+        let id: i64 = sqlx::query_scalar(
             r#"
-            INSERT INTO chats (ws_id, name, chat_type, members)
-            VALUES ($1, $2, $3, $4)
-            RETURNING id, ws_id, name, chat_type, members, created_at
+            INSERT INTO chats (ws_id, name, chat_type)
+            VALUES ($1, $2, $3)
+            RETURNING id
             "#,
         )
         .bind(ws_id as i64)
         .bind(input.name)
         .bind(chat_type)
+        .fetch_one(&mut *tx)
+        .await?;
+        // the creator owns the chat
+        sqlx::query(
+            r#"
+            INSERT INTO chat_members (chat_id, user_id, role)
+            SELECT $1, m, CASE WHEN m = $3 THEN 'owner'::chat_role ELSE 'member'::chat_role END
+            FROM unnest($2::bigint[]) m
+            "#,
+        )
+        .bind(id)
         .bind(&input.members)
-        .fetch_one(&self.pg_pool)
+        .bind(user_id as i64)
+        .execute(&mut *tx)
         .await?;
+        tx.commit().await?;
+
+        let chat = self
+            .get_chat_by_id(id as _)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {id}")))?;
         Ok(chat)
     }
-    pub async fn fetch_chats(&self, user_id: u64, ws_id: u64) -> Result<Vec<Chat>, AppError> {
+    /// The single chat between the two users, created if it doesn't exist yet.
+    /// Returns whether the chat was created.
+    pub async fn get_or_create_dm(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+        other_id: u64,
+    ) -> Result<(Chat, bool), AppError> {
+        if user_id == other_id {
+            return Err(AppError::CreateChatError(
+                "Can't start a direct message with yourself".to_string(),
+            ));
+        }
+        let users = self
+            .fetch_chat_user_by_ids(ws_id as _, &[other_id as _])
+            .await?;
+        if users.is_empty() {
+            return Err(AppError::NotFound(format!("user id {other_id}")));
+        }
+        let dm_key = dm_key(user_id, other_id);
+        let mut tx = self.pg_pool.begin().await?;
+        // concurrent requests wait on the unique index, the loser finds the chat below
+        let id: Option<i64> = sqlx::query_scalar(
+            r#"
+            INSERT INTO chats (ws_id, chat_type, dm_key)
+            VALUES ($1, 'single', $2)
+            ON CONFLICT (ws_id, dm_key) DO NOTHING
+            RETURNING id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(&dm_key)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let created = id.is_some();
+        let id = match id {
+            Some(id) => {
+                // the creator owns the chat
+                sqlx::query(
+                    r#"
+                    INSERT INTO chat_members (chat_id, user_id, role)
+                    VALUES ($1, $2, 'owner'), ($1, $3, 'member')
+                    "#,
+                )
+                .bind(id)
+                .bind(user_id as i64)
+                .bind(other_id as i64)
+                .execute(&mut *tx)
+                .await?;
+                tx.commit().await?;
+                id
+            }
+            None => {
+                tx.rollback().await?;
+                sqlx::query_scalar("SELECT id FROM chats WHERE ws_id = $1 AND dm_key = $2")
+                    .bind(ws_id as i64)
+                    .bind(&dm_key)
+                    .fetch_one(&self.pg_pool)
+                    .await?
+            }
+        };
+        let chat = self
+            .get_chat_by_id(id as _)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {id}")))?;
+        Ok((chat, created))
+    }
+
+    /// Chats of the user, most recently active first.
+    pub async fn fetch_chats(
+        &self,
+        user_id: u64,
+        ws_id: u64,
+        input: ListChats,
+    ) -> Result<Vec<ChatListItem>, AppError> {
+        let limit = match input.limit {
+            1..=100 => input.limit as _,
+            _ => 100i64,
+        };
         let chats = sqlx::query_as(
             r#"
-            SELECT id, ws_id, name, chat_type, members, created_at
-            FROM chats
-            WHERE ws_id = $1 AND $2 = ANY(members)
+            SELECT c.id, c.ws_id, c.name, c.chat_type, chat_member_ids(c.id) AS members,
+              c.created_at, c.archived_at, c.message_ttl_secs, m.last_read_message_id,
+              unread.unread_count, unread.mention_count, c.last_message_at,
+              COALESCE((
+                SELECT jsonb_build_object('id', lm.id, 'senderId', lm.sender_id,
+                  'content', left(lm.content, $6), 'createdAt', lm.created_at,
+                  'deletedAt', lm.deleted_at)
+                FROM visible_messages lm
+                WHERE lm.id = c.last_message_id
+              ), 'null') AS last_message
+            FROM chats c
+            JOIN chat_members m ON m.chat_id = c.id
+            CROSS JOIN LATERAL (
+              SELECT count(*) AS unread_count,
+                count(*) FILTER (WHERE EXISTS (
+                  SELECT 1 FROM message_mentions mm
+                  WHERE mm.message_id = msg.id AND mm.user_id = m.user_id
+                )) AS mention_count
+              FROM visible_messages msg
+              WHERE msg.chat_id = c.id AND msg.id > COALESCE(m.last_read_message_id, 0)
+                AND msg.sender_id <> m.user_id AND msg.deleted_at IS NULL
+            ) unread
+            WHERE c.ws_id = $1 AND m.user_id = $2 AND ($3 OR c.archived_at IS NULL)
+              AND ($4::bigint IS NULL OR (COALESCE(c.last_message_at, c.created_at), c.id) < (
+                SELECT COALESCE(last_message_at, created_at), id FROM chats WHERE id = $4
+              ))
+            ORDER BY COALESCE(c.last_message_at, c.created_at) DESC, c.id DESC
+            LIMIT $5
             "#,
         )
         .bind(ws_id as i64)
         .bind(user_id as i64)
+        .bind(input.archived)
+        .bind(input.last_id.map(|v| v as i64))
+        .bind(limit)
+        .bind(PREVIEW_LENGTH)
         .fetch_all(&self.pg_pool)
         .await?;
         Ok(chats)
@@ -101,7 +309,8 @@ impl AppState {
     pub async fn get_chat_by_id(&self, id: u64) -> Result<Option<Chat>, AppError> {
         let chat = sqlx::query_as(
             r#"
-            SELECT id, ws_id, name, chat_type, members, created_at
+            SELECT id, ws_id, name, chat_type, chat_member_ids(id) AS members, created_at,
+              archived_at, message_ttl_secs
             FROM chats
             WHERE id = $1
             "#,
@@ -112,26 +321,44 @@ impl AppState {
         Ok(chat)
     }
 
-    pub async fn fetch_and_verify_chat(&self, chat_id: u64, user: &User) -> Result<Chat, AppError> {
+    /// Fetch the chat and check the user is allowed to perform the action on it.
+    pub async fn fetch_and_verify_chat(
+        &self,
+        chat_id: u64,
+        user: &User,
+        action: Action,
+    ) -> Result<Chat, AppError> {
         let chat = self
             .get_chat_by_id(chat_id)
             .await?
             .ok_or(AppError::NotFound(format!("Chat id {}", chat_id)))?;
-
-        // check if user is a member of the chat
-        if chat.ws_id != user.ws_id {
-            return Err(AppError::Unauthorized(
-                "You do not have permission to update this chat.".into(),
-            ));
-        }
-
+        self.authorize_chat(user, &chat, action).await?;
         Ok(chat)
     }
     pub async fn apply_updates(&self, chat: &mut Chat, input: UpdateChat) -> Result<(), AppError> {
+        // a direct message is found by the key of its two members
+        if chat.chat_type == ChatType::Single && (input.members.is_some() || input.public.is_some())
+        {
+            return Err(AppError::UpdateChatError(
+                "Members and type of a single chat can't be changed".to_string(),
+            ));
+        }
         if let Some(name) = input.name {
             chat.name = Some(name);
         }
-        if let Some(members) = input.members {
+        if let Some(ttl) = input.message_ttl_secs {
+            chat.message_ttl_secs = match ttl {
+                0 => None,
+                1..=MAX_MESSAGE_TTL_SECS => Some(ttl as _),
+                _ => {
+                    return Err(AppError::UpdateChatError(format!(
+                        "Time to live can be at most {MAX_MESSAGE_TTL_SECS} seconds"
+                    )))
+                }
+            };
+        }
+        let members = input.members;
+        if let Some(members) = &members {
             let len = members.len();
             if len < 2 {
                 return Err(AppError::UpdateChatError(
@@ -143,13 +370,12 @@ impl AppState {
                     "Group chat with more than 8 members must have a name".to_string(),
                 ));
             }
-            let users = self.fetch_chat_user_by_ids(&members).await?;
+            let users = self.fetch_chat_user_by_ids(chat.ws_id, members).await?;
             if users.len() != len {
                 return Err(AppError::UpdateChatError(
                     "Some members do not exist".to_string(),
                 ));
             }
-            chat.members = members;
         }
         if let Some(public) = input.public {
             chat.chat_type = if public {
@@ -157,27 +383,171 @@ impl AppState {
             } else {
                 ChatType::PrivateChannel
             };
-        } else {
-            // if no public field is provided, update chat type based on the number of members
-            chat.chat_type = match (&chat.name, chat.members.len()) {
-                (None, 2) => ChatType::Single,
-                (None, _) => ChatType::Group,
-                (Some(_), _) => chat.chat_type.clone(), // keep the current chat type
-            };
+        } else if chat.name.is_none() && chat.chat_type != ChatType::Single {
+            // a group down to 2 members stays a group, direct messages are only created
+            // by get_or_create_dm
+            chat.chat_type = ChatType::Group;
+        }
+        let mut tx = self.pg_pool.begin().await?;
+        if let Some(members) = &members {
+            sqlx::query("DELETE FROM chat_members WHERE chat_id = $1 AND NOT user_id = ANY($2)")
+                .bind(chat.id)
+                .bind(members)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(
+                r#"
+                INSERT INTO chat_members (chat_id, user_id)
+                SELECT $1, unnest($2::bigint[])
+                ON CONFLICT DO NOTHING
+                "#,
+            )
+            .bind(chat.id)
+            .bind(members)
+            .execute(&mut *tx)
+            .await?;
         }
         sqlx::query(
             r#"
         UPDATE chats
-        SET name = $1, type = $2, members = $3
-        WHERE id = $4
+        SET name = $1, chat_type = $2, message_ttl_secs = $4
+        WHERE id = $3
         "#,
         )
         .bind(&chat.name)
         .bind(&chat.chat_type)
-        .bind(&chat.members)
         .bind(chat.id)
+        .bind(chat.message_ttl_secs)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        if let Some(mut members) = members {
+            members.sort_unstable();
+            chat.members = members;
+        }
+        Ok(())
+    }
+
+    /// Archive or restore a chat, archived chats stay readable but take no new messages.
+    pub async fn set_chat_archived(&self, chat: &mut Chat, archived: bool) -> Result<(), AppError> {
+        let archived_at = sqlx::query_scalar(
+            r#"
+            UPDATE chats
+            SET archived_at = CASE WHEN $2 THEN COALESCE(archived_at, now()) END
+            WHERE id = $1
+            RETURNING archived_at
+            "#,
+        )
+        .bind(chat.id)
+        .bind(archived)
+        .fetch_one(&self.pg_pool)
+        .await?;
+        chat.archived_at = archived_at;
+        Ok(())
+    }
+
+    /// Delete the chat with all of its messages. Returns the files of the deleted
+    /// messages that no other message refers to, so the caller can remove them.
+    pub async fn purge_chat(&self, chat_id: i64) -> Result<Vec<String>, AppError> {
+        let mut tx = self.pg_pool.begin().await?;
+        let files: Vec<String> = sqlx::query_scalar(
+            r#"
+            WITH deleted AS (
+              DELETE FROM messages
+              WHERE chat_id = $1
+              RETURNING files
+            )
+            SELECT DISTINCT f
+            FROM deleted, unnest(files) f
+            "#,
+        )
+        .bind(chat_id)
+        .fetch_all(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM chats WHERE id = $1")
+            .bind(chat_id)
+            .execute(&mut *tx)
+            .await?;
+        let shared: Vec<String> = sqlx::query_scalar("SELECT message_files_in_use($1)")
+            .bind(&files)
+            .fetch_all(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(files.into_iter().filter(|f| !shared.contains(f)).collect())
+    }
+
+    /// Public channels of the workspace with their member counts.
+    pub async fn fetch_public_channels(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<ChannelSummary>, AppError> {
+        self.authorize(ws_id, user_id, Action::JoinChannel).await?;
+        let channels = sqlx::query_as(
+            r#"
+            SELECT c.id, c.ws_id, c.name, count(m.user_id) AS member_count,
+              COALESCE(bool_or(m.user_id = $2), false) AS joined, c.created_at
+            FROM chats c
+            LEFT JOIN chat_members m ON m.chat_id = c.id
+            WHERE c.ws_id = $1 AND c.chat_type = 'public_channel' AND c.archived_at IS NULL
+            GROUP BY c.id
+            ORDER BY c.name, c.id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(channels)
+    }
+
+    /// Join a public channel of the user's workspace, joining twice is a no-op.
+    pub async fn join_chat(&self, chat_id: u64, user: &User) -> Result<Chat, AppError> {
+        let chat = self
+            .get_chat_by_id(chat_id)
+            .await?
+            .filter(|chat| chat.ws_id == user.ws_id)
+            .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))?;
+        if chat.chat_type != ChatType::PublicChannel || chat.archived_at.is_some() {
+            return Err(AppError::Forbidden(
+                "Only active public channels can be joined".to_string(),
+            ));
+        }
+        self.authorize(user.ws_id as _, user.id as _, Action::JoinChannel)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO chat_members (chat_id, user_id, last_read_message_id)
+            SELECT $1, $2, max(id) FROM messages WHERE chat_id = $1
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(chat.id)
+        .bind(user.id)
         .execute(&self.pg_pool)
         .await?;
+        self.get_chat_by_id(chat_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))
+    }
+
+    /// Leave a channel or group chat, direct messages can't be left.
+    pub async fn leave_chat(&self, chat_id: u64, user_id: u64) -> Result<(), AppError> {
+        let chat = self
+            .get_chat_by_id(chat_id)
+            .await?
+            .filter(|chat| chat.members.contains(&(user_id as i64)))
+            .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))?;
+        if chat.chat_type == ChatType::Single {
+            return Err(AppError::UpdateChatError(
+                "Can't leave a single chat".to_string(),
+            ));
+        }
+        sqlx::query("DELETE FROM chat_members WHERE chat_id = $1 AND user_id = $2")
+            .bind(chat.id)
+            .bind(user_id as i64)
+            .execute(&self.pg_pool)
+            .await?;
         Ok(())
     }
 
@@ -185,8 +555,8 @@ impl AppState {
         let is_member = sqlx::query(
             r#"
             SELECT 1
-            FROM chats
-            WHERE id = $1 AND $2 = ANY(members)
+            FROM chat_members
+            WHERE chat_id = $1 AND user_id = $2
             "#,
         )
         .bind(chat_id as i64)
@@ -213,16 +583,21 @@ impl CreateChat {
     }
 }
 
+fn dm_key(a: u64, b: u64) -> String {
+    format!("{}:{}", a.min(b), a.max(b))
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::*;
-    use crate::AppState;
+    use crate::{models::message::CreateMessage, AppState};
+    use chat_core::ChatRole;
 
     #[tokio::test]
     async fn create_single_chat_should_work() -> anyhow::Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
-        let input = CreateChat::new("", &[1, 2], false);
+        let input = CreateChat::new("", &[1, 3], false);
         let chat = state
             .create_chat(input, 1, 1)
             .await
@@ -230,6 +605,8 @@ mod tests {
         assert_eq!(chat.ws_id, 1);
         assert_eq!(chat.members.len(), 2);
         assert_eq!(chat.chat_type, ChatType::Single);
+        assert_eq!(state.chat_role(chat.id, 1).await?, Some(ChatRole::Owner));
+        assert_eq!(state.chat_role(chat.id, 3).await?, Some(ChatRole::Member));
         Ok(())
     }
     #[tokio::test]
@@ -246,6 +623,129 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn apply_updates_should_replace_members() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut chat = state.get_chat_by_id(4).await?.expect("chat should exist");
+        let input = UpdateChat {
+            name: None,
+            members: Some(vec![4, 1, 5]),
+            public: None,
+            message_ttl_secs: None,
+        };
+        state.apply_updates(&mut chat, input).await?;
+        assert_eq!(chat.members, vec![1, 4, 5]);
+        let mut chat = state.get_chat_by_id(4).await?.expect("chat should exist");
+        assert_eq!(chat.members, vec![1, 4, 5]);
+        assert!(!state.is_chat_member(4, 3).await?);
+        // the creator left, the remaining members keep their role
+        assert_eq!(state.chat_role(4, 1).await?, Some(ChatRole::Member));
+
+        let input = UpdateChat {
+            name: None,
+            members: Some(vec![1, 4]),
+            public: None,
+            message_ttl_secs: None,
+        };
+        state.apply_updates(&mut chat, input).await?;
+        assert_eq!(chat.chat_type, ChatType::Group);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn apply_updates_should_keep_single_chat() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut chat = state.get_chat_by_id(3).await?.expect("chat should exist");
+        let input = UpdateChat {
+            name: None,
+            members: Some(vec![1, 3]),
+            public: None,
+            message_ttl_secs: None,
+        };
+        let ret = state.apply_updates(&mut chat, input).await;
+        assert!(matches!(ret, Err(AppError::UpdateChatError(_))));
+        let input = UpdateChat {
+            name: None,
+            members: None,
+            public: Some(true),
+            message_ttl_secs: None,
+        };
+        let ret = state.apply_updates(&mut chat, input).await;
+        assert!(matches!(ret, Err(AppError::UpdateChatError(_))));
+
+        let (dm, _) = state.get_or_create_dm(1, 1, 2).await?;
+        assert_eq!(dm.id, 3);
+        assert_eq!(dm.members, vec![1, 2]);
+        assert_eq!(dm.chat_type, ChatType::Single);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn join_and_leave_channel_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateChat::new("random", &[1, 2], true);
+        let chat = state.create_chat(input, 1, 1).await?;
+        let channels = state.fetch_public_channels(1, 3).await?;
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[1].name, "random");
+        assert_eq!(channels[1].member_count, 2);
+        assert!(!channels[1].joined);
+
+        let user = state.find_user_by_id(3).await?.expect("user should exist");
+        let chat = state.join_chat(chat.id as _, &user).await?;
+        assert_eq!(chat.members, vec![1, 2, 3]);
+        // private channels can't be joined
+        let ret = state
+            .join_chat(2, &state.find_user_by_id(4).await?.unwrap())
+            .await;
+        assert!(matches!(ret, Err(AppError::Forbidden(_))));
+
+        state.leave_chat(chat.id as _, 3).await?;
+        assert!(!state.is_chat_member(chat.id as _, 3).await?);
+        let ret = state.leave_chat(chat.id as _, 3).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        let ret = state.leave_chat(3, 1).await;
+        assert!(matches!(ret, Err(AppError::UpdateChatError(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn archive_and_purge_chat_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut chat = state.get_chat_by_id(1).await?.expect("chat should exist");
+        state.set_chat_archived(&mut chat, true).await?;
+        assert!(chat.archived_at.is_some());
+        let chats = state.fetch_chats(1, 1, ListChats::default()).await?;
+        assert_eq!(chats.len(), 3);
+        let chats = state
+            .fetch_chats(
+                1,
+                1,
+                ListChats {
+                    archived: true,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        assert_eq!(chats.len(), 4);
+        assert!(state.fetch_public_channels(1, 1).await?.is_empty());
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![],
+            ..Default::default()
+        };
+        let ret = state.create_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+        state.set_chat_archived(&mut chat, false).await?;
+        assert!(chat.archived_at.is_none());
+
+        // chats with history can be purged
+        let files = state.purge_chat(1).await?;
+        assert!(files.is_empty());
+        assert!(state.get_chat_by_id(1).await?.is_none());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn chat_get_by_id_should_work() -> anyhow::Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
@@ -264,10 +764,81 @@ mod tests {
     async fn chat_fetch_all_should_work() -> anyhow::Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let chats = state
-            .fetch_chats(1, 1)
+            .fetch_chats(1, 1, ListChats::default())
             .await
             .expect("fetch all chats failed");
         assert_eq!(chats.len(), 4);
+        let general = chats.iter().find(|c| c.chat.id == 1).unwrap();
+        let preview = general.last_message.as_ref().unwrap();
+        assert_eq!(general.last_message_at, Some(preview.created_at));
+        assert_eq!(preview.id, 10);
+        assert!(chats
+            .iter()
+            .find(|c| c.chat.id == 4)
+            .unwrap()
+            .last_message
+            .is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn get_or_create_dm_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // Ivena and Alice already have a single chat
+        let (chat, created) = state.get_or_create_dm(1, 2, 1).await?;
+        assert!(!created);
+        assert_eq!(chat.id, 3);
+
+        let (chat, created) = state.get_or_create_dm(1, 1, 5).await?;
+        assert!(created);
+        assert_eq!(chat.chat_type, ChatType::Single);
+        assert_eq!(chat.members, vec![1, 5]);
+        let input = CreateChat::new("", &[5, 1], false);
+        let same = state.create_chat(input, 5, 1).await?;
+        assert_eq!(same.id, chat.id);
+
+        let (a, b) = tokio::join!(
+            state.get_or_create_dm(1, 3, 5),
+            state.get_or_create_dm(1, 5, 3)
+        );
+        let ((a, a_created), (b, b_created)) = (a?, b?);
+        assert_eq!(a.id, b.id);
+        assert!(a_created ^ b_created);
+
+        let ret = state.get_or_create_dm(1, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateChatError(_))));
+        let ret = state.get_or_create_dm(1, 1, 100).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_fetch_should_sort_by_activity_and_paginate() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            ..Default::default()
+        };
+        let message = state.create_message(input, 3, 1).await?;
+        let input = ListChats {
+            limit: 2,
+            ..Default::default()
+        };
+        let chats = state.fetch_chats(1, 1, input).await?;
+        assert_eq!(chats.len(), 2);
+        assert_eq!(chats[0].chat.id, 3);
+        assert_eq!(chats[0].last_message.as_ref().unwrap().id, message.id);
+
+        let input = ListChats {
+            last_id: Some(chats[1].chat.id as _),
+            limit: 2,
+            ..Default::default()
+        };
+        let next = state.fetch_chats(1, 1, input).await?;
+        assert_eq!(next.len(), 2);
+        let mut ids: Vec<_> = chats.iter().chain(next.iter()).map(|c| c.chat.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
         Ok(())
     }
     #[tokio::test]
@@ -0,0 +1,190 @@
+use std::{str::FromStr, time::Duration};
+
+use tokio::fs;
+use tracing::{info, warn};
+
+use crate::{models::ChatFile, AppError, AppState};
+
+const REAPER_INTERVAL: Duration = Duration::from_secs(10);
+const REAPER_BATCH_SIZE: i64 = 500;
+
+impl AppState {
+    /// Turn expired messages into tombstones like `delete_message` does, members get a
+    /// deletion event from the message trigger. Files no other message uses are removed.
+    /// Returns how many messages expired.
+    pub async fn reap_expired_messages(&self) -> Result<usize, AppError> {
+        let mut tx = self.pg_pool.begin().await?;
+        let expired: Vec<(i64, Vec<String>)> = sqlx::query_as(
+            r#"
+            SELECT id, files
+            FROM messages
+            WHERE expires_at <= now() AND deleted_at IS NULL
+            ORDER BY expires_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+            "#,
+        )
+        .bind(REAPER_BATCH_SIZE)
+        .fetch_all(&mut *tx)
+        .await?;
+        if expired.is_empty() {
+            return Ok(0);
+        }
+        let ids: Vec<i64> = expired.iter().map(|(id, _)| *id).collect();
+        let mut files: Vec<String> = expired.into_iter().flat_map(|(_, f)| f).collect();
+        files.sort();
+        files.dedup();
+        for table in ["message_edits", "message_reactions", "chat_pins"] {
+            sqlx::query(&format!("DELETE FROM {table} WHERE message_id = ANY($1)"))
+                .bind(&ids)
+                .execute(&mut *tx)
+                .await?;
+        }
+        sqlx::query(
+            r#"
+            UPDATE messages
+            SET content = '', content_html = '', files = '{}', reference = NULL,
+              deleted_at = expires_at
+            WHERE id = ANY($1)
+            "#,
+        )
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?;
+        let shared: Vec<String> = sqlx::query_scalar("SELECT message_files_in_use($1)")
+            .bind(&files)
+            .fetch_all(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        // the messages are gone already, a file left behind is only wasted space
+        let base_dir = &self.config.server.base_dir;
+        for s in files.iter().filter(|f| !shared.contains(f)) {
+            let Ok(file) = ChatFile::from_str(s) else {
+                continue;
+            };
+            if let Err(e) = fs::remove_file(file.path(base_dir)).await {
+                warn!("Failed to remove file {}: {}", s, e);
+            }
+        }
+        Ok(ids.len())
+    }
+}
+
+/// Start the background worker deleting expired messages, once per server.
+pub fn setup_reaper(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REAPER_INTERVAL);
+        loop {
+            interval.tick().await;
+            match state.reap_expired_messages().await {
+                Ok(0) => {}
+                Ok(n) => info!("Deleted {} expired messages", n),
+                Err(e) => warn!("Failed to delete expired messages: {:?}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        chat::UpdateChat,
+        message::{CreateMessage, ListMessages},
+    };
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn expired_messages_should_be_hidden_and_reaped() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let file = ChatFile::new(1, "secret.txt", b"hunter2");
+        let path = file.path(&state.config.server.base_dir);
+        std::fs::create_dir_all(path.parent().expect("file path parent should exists"))?;
+        std::fs::write(&path, "hunter2")?;
+        let input = CreateMessage {
+            content: "the password is hunter2".to_string(),
+            files: vec![file.url()],
+            ttl_secs: Some(60),
+            ..Default::default()
+        };
+        let message = state.create_message(input, 1, 1).await?;
+        assert!(message.expires_at.is_some());
+        sqlx::query("UPDATE messages SET expires_at = now() WHERE id = $1")
+            .bind(message.id)
+            .execute(&state.pg_pool)
+            .await?;
+
+        // expired content is never returned, even before the reaper ran
+        let page = state.list_messages(ListMessages::default(), 1).await?;
+        assert_eq!(page.messages[0].id, message.id);
+        assert!(page.messages[0].content.is_empty());
+        assert!(page.messages[0].files.is_empty());
+        assert!(page.messages[0].deleted_at.is_some());
+
+        assert_eq!(state.reap_expired_messages().await?, 1);
+        assert_eq!(state.reap_expired_messages().await?, 0);
+        let content: String = sqlx::query_scalar("SELECT content FROM messages WHERE id = $1")
+            .bind(message.id)
+            .fetch_one(&state.pg_pool)
+            .await?;
+        assert!(content.is_empty());
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expired_replies_should_leave_the_thread() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "gone soon".to_string(),
+            parent_id: Some(1),
+            ttl_secs: Some(60),
+            ..Default::default()
+        };
+        let reply = state.create_message(input, 1, 2).await?;
+        let parent = state.get_message_by_id(1, 1).await?;
+        assert_eq!(parent.reply_count, 1);
+        sqlx::query("UPDATE messages SET expires_at = now() WHERE id = $1")
+            .bind(reply.id)
+            .execute(&state.pg_pool)
+            .await?;
+
+        assert_eq!(state.reap_expired_messages().await?, 1);
+        let parent = state.get_message_by_id(1, 1).await?;
+        assert_eq!(parent.reply_count, 0);
+        assert_eq!(parent.last_reply_at, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_ttl_should_apply_to_new_messages() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut chat = state.get_chat_by_id(3).await?.expect("chat should exist");
+        let input = UpdateChat {
+            name: None,
+            members: None,
+            public: None,
+            message_ttl_secs: Some(3600),
+        };
+        state.apply_updates(&mut chat, input).await?;
+        let chat = state.get_chat_by_id(3).await?.expect("chat should exist");
+        assert_eq!(chat.message_ttl_secs, Some(3600));
+        let input = CreateMessage {
+            content: "temporary".to_string(),
+            ..Default::default()
+        };
+        let message = state.create_message(input, 3, 1).await?;
+        let ttl = message.expires_at.expect("message should expire") - message.created_at;
+        assert_eq!(ttl.num_seconds(), 3600);
+
+        let input = CreateMessage {
+            content: "forever".to_string(),
+            ttl_secs: Some(0),
+            ..Default::default()
+        };
+        let ret = state.create_message(input, 3, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+        Ok(())
+    }
+}
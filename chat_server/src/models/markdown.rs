@@ -0,0 +1,319 @@
+use crate::models::mention::{match_mention, Mention};
+
+/// in characters, after normalizing
+pub(crate) const MAX_CONTENT_LENGTH: usize = 10_000;
+const MAX_LANGUAGE_LENGTH: usize = 32;
+const LINK_SCHEMES: [&str; 3] = ["http://", "https://", "mailto:"];
+
+/// Canonical form of the message source: unix line breaks, no control characters, no
+/// trailing whitespace or repeated blank lines outside of code blocks, and code blocks
+/// are always closed.
+pub(crate) fn normalize(content: &str) -> String {
+    let content = content.replace("\r\n", "\n").replace('\r', "\n");
+    let mut lines: Vec<String> = Vec::new();
+    let mut in_code = false;
+    for line in content.split('\n') {
+        let line: String = line
+            .chars()
+            .filter(|c| *c == '\t' || !c.is_control())
+            .collect();
+        if in_code {
+            in_code = !is_closing_fence(&line);
+            lines.push(line);
+            continue;
+        }
+        let line = line.trim_end();
+        if opening_fence(line).is_some() {
+            in_code = true;
+        } else if line.is_empty() && lines.last().is_none_or(|l| l.is_empty()) {
+            continue;
+        }
+        lines.push(line.to_string());
+    }
+    if in_code {
+        lines.push("```".to_string());
+    }
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+/// Render normalized content to html. The supported subset is paragraphs, fenced code
+/// blocks, `code`, **bold**, *italic*, _italic_, ~~strikethrough~~, [links](url), bare
+/// urls and @mentions of the chat members.
+///
+/// Text is always escaped and raw html is never passed through, the only tags produced
+/// are p, br, pre, code, strong, em, del, a and span. Links are limited to http, https
+/// and mailto, so the html can be inserted into a page as is.
+pub(crate) fn render_html(content: &str, members: &[(i64, String)]) -> String {
+    render(content, members).0
+}
+
+/// The mentions in the content, in order. Mentions inside code or urls are rendered as
+/// text, so they don't count either.
+pub(crate) fn find_mentions(content: &str, members: &[(i64, String)]) -> Vec<Mention> {
+    render(content, members).1
+}
+
+/// The members that can be mentioned, and the mentions rendered so far.
+struct Mentions<'a> {
+    members: &'a [(i64, String)],
+    found: Vec<Mention>,
+}
+
+fn render(content: &str, members: &[(i64, String)]) -> (String, Vec<Mention>) {
+    let mut mentions = Mentions {
+        members,
+        found: Vec::new(),
+    };
+    let mut html = String::new();
+    let mut paragraph = Vec::new();
+    let mut lines = content.lines();
+    while let Some(line) = lines.next() {
+        if let Some(lang) = opening_fence(line) {
+            render_paragraph(&mut html, &paragraph, &mut mentions);
+            paragraph.clear();
+            let code: Vec<_> = lines
+                .by_ref()
+                .take_while(|l| !is_closing_fence(l))
+                .collect();
+            render_code_block(&mut html, lang, &code);
+        } else if line.trim().is_empty() {
+            render_paragraph(&mut html, &paragraph, &mut mentions);
+            paragraph.clear();
+        } else {
+            paragraph.push(line);
+        }
+    }
+    render_paragraph(&mut html, &paragraph, &mut mentions);
+    (html, mentions.found)
+}
+
+fn opening_fence(line: &str) -> Option<&str> {
+    line.trim_start().strip_prefix("```").map(str::trim)
+}
+
+fn is_closing_fence(line: &str) -> bool {
+    line.trim() == "```"
+}
+
+fn render_code_block(html: &mut String, lang: &str, code: &[&str]) {
+    let valid = !lang.is_empty()
+        && lang.len() <= MAX_LANGUAGE_LENGTH
+        && lang
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-#._".contains(c));
+    if valid {
+        html.push_str(&format!("<pre><code class=\"language-{lang}\">"));
+    } else {
+        html.push_str("<pre><code>");
+    }
+    escape_into(html, &code.join("\n"));
+    html.push_str("</code></pre>");
+}
+
+fn render_paragraph(html: &mut String, lines: &[&str], mentions: &mut Mentions) {
+    if lines.is_empty() {
+        return;
+    }
+    html.push_str("<p>");
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            html.push_str("<br>");
+        }
+        render_inline(html, line, mentions);
+    }
+    html.push_str("</p>");
+}
+
+fn render_inline(html: &mut String, text: &str, mentions: &mut Mentions) {
+    let mut rest = text;
+    let mut prev = None;
+    while let Some(c) = rest.chars().next() {
+        let len = render_span(html, rest, prev, mentions).unwrap_or_else(|| {
+            escape_into(html, &rest[..c.len_utf8()]);
+            c.len_utf8()
+        });
+        prev = rest[..len].chars().last();
+        rest = &rest[len..];
+    }
+}
+
+/// Render the markup the text starts with, returns how many bytes of the text it used.
+fn render_span(
+    html: &mut String,
+    text: &str,
+    prev: Option<char>,
+    mentions: &mut Mentions,
+) -> Option<usize> {
+    let after_word = prev.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    match text.as_bytes()[0] {
+        b'`' => {
+            let len = text[1..].find('`').filter(|len| *len > 0)?;
+            html.push_str("<code>");
+            escape_into(html, &text[1..=len]);
+            html.push_str("</code>");
+            Some(len + 2)
+        }
+        b'*' if text.starts_with("**") => render_emphasis(html, text, "**", "strong", mentions),
+        b'~' if text.starts_with("~~") => render_emphasis(html, text, "~~", "del", mentions),
+        b'*' => render_emphasis(html, text, "*", "em", mentions),
+        // snake_case words are no emphasis
+        b'_' if !after_word => render_emphasis(html, text, "_", "em", mentions),
+        b'[' => render_link(html, text),
+        b'h' if !after_word => render_autolink(html, text),
+        // an @ inside a word is an email address or the like
+        b'@' if !after_word => render_mention(html, text, mentions),
+        _ => None,
+    }
+}
+
+fn render_emphasis(
+    html: &mut String,
+    text: &str,
+    delim: &str,
+    tag: &str,
+    mentions: &mut Mentions,
+) -> Option<usize> {
+    let inner = &text[delim.len()..];
+    let body = &inner[..inner.find(delim)?];
+    if body.is_empty()
+        || body.starts_with(char::is_whitespace)
+        || body.ends_with(char::is_whitespace)
+    {
+        return None;
+    }
+    html.push_str(&format!("<{tag}>"));
+    render_inline(html, body, mentions);
+    html.push_str(&format!("</{tag}>"));
+    Some(body.len() + delim.len() * 2)
+}
+
+fn render_link(html: &mut String, text: &str) -> Option<usize> {
+    let label_end = text.find("](")?;
+    let label = &text[1..label_end];
+    let url_start = label_end + 2;
+    let url = &text[url_start..url_start + text[url_start..].find(')')?];
+    if label.is_empty() || !is_safe_url(url) {
+        return None;
+    }
+    push_link(html, url, label);
+    Some(url_start + url.len() + 1)
+}
+
+fn render_autolink(html: &mut String, text: &str) -> Option<usize> {
+    if !text.starts_with("http://") && !text.starts_with("https://") {
+        return None;
+    }
+    let end = text
+        .find(|c: char| c.is_whitespace() || "<>\"".contains(c))
+        .unwrap_or(text.len());
+    // punctuation after a url belongs to the sentence
+    let url = text[..end].trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '\'']);
+    if !is_safe_url(url) {
+        return None;
+    }
+    push_link(html, url, url);
+    Some(url.len())
+}
+
+fn render_mention(html: &mut String, text: &str, mentions: &mut Mentions) -> Option<usize> {
+    let (mention, len) = match_mention(&text[1..], mentions.members)?;
+    let attr = match mention {
+        Mention::All(name) => format!("data-mention=\"{name}\""),
+        Mention::User(id) => format!("data-user-id=\"{id}\""),
+    };
+    mentions.found.push(mention);
+    html.push_str(&format!("<span class=\"mention\" {attr}>"));
+    escape_into(html, &text[..=len]);
+    html.push_str("</span>");
+    Some(len + 1)
+}
+
+fn is_safe_url(url: &str) -> bool {
+    let lower = url.to_ascii_lowercase();
+    LINK_SCHEMES
+        .iter()
+        .any(|scheme| lower.starts_with(scheme) && lower.len() > scheme.len())
+        && !url.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+fn push_link(html: &mut String, url: &str, label: &str) {
+    html.push_str("<a href=\"");
+    escape_into(html, url);
+    html.push_str("\" target=\"_blank\" rel=\"noopener noreferrer nofollow\">");
+    escape_into(html, label);
+    html.push_str("</a>");
+}
+
+fn escape_into(html: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_should_work() {
+        let content = "\r\n\r\nhello  \r\n\n\n\nworld\u{7}\n```rust\nfn main() {  \n\n\n}";
+        assert_eq!(
+            normalize(content),
+            "hello\n\nworld\n```rust\nfn main() {  \n\n\n}\n```"
+        );
+        assert_eq!(normalize(" \n\t\n"), "");
+    }
+
+    #[test]
+    fn render_html_should_work() {
+        let members = vec![(2, "Alice Chen".to_string()), (3, "Alice".to_string())];
+        let content = "**release** is _out_, see [notes](https://acme.org/notes) or\nhttps://acme.org/a?b=1&c=2. ping @alice chen @here\n\n```rust\nlet a = \"<b>\";\n```\nuse `snake_case_name` ~~not~~ *this*";
+        assert_eq!(
+            render_html(content, &members),
+            concat!(
+                "<p><strong>release</strong> is <em>out</em>, see ",
+                "<a href=\"https://acme.org/notes\" target=\"_blank\" rel=\"noopener noreferrer nofollow\">notes</a> or<br>",
+                "<a href=\"https://acme.org/a?b=1&amp;c=2\" target=\"_blank\" rel=\"noopener noreferrer nofollow\">https://acme.org/a?b=1&amp;c=2</a>. ",
+                "ping <span class=\"mention\" data-user-id=\"2\">@alice chen</span> ",
+                "<span class=\"mention\" data-mention=\"here\">@here</span></p>",
+                "<pre><code class=\"language-rust\">let a = &quot;&lt;b&gt;&quot;;</code></pre>",
+                "<p>use <code>snake_case_name</code> <del>not</del> <em>this</em></p>",
+            )
+        );
+        assert_eq!(render_html("2 * 3 * 4 = 24", &[]), "<p>2 * 3 * 4 = 24</p>");
+        assert_eq!(
+            render_html("ops@channel.io", &members),
+            "<p>ops@channel.io</p>"
+        );
+    }
+
+    #[test]
+    fn render_html_should_escape_markup() {
+        let content = "<script>alert(1)</script> <img src=x onerror=alert(1)>";
+        assert_eq!(
+            render_html(content, &[]),
+            "<p>&lt;script&gt;alert(1)&lt;/script&gt; &lt;img src=x onerror=alert(1)&gt;</p>"
+        );
+
+        // only http, https and mailto links
+        let html = render_html("[x](javascript:alert(1)) [y](JAVASCRIPT:alert(1))", &[]);
+        assert!(!html.contains("<a"));
+        let html = render_html("[x](https://a.org/\"onmouseover=\"alert(1))", &[]);
+        assert!(html.contains("href=\"https://a.org/&quot;onmouseover=&quot;alert(1\""));
+        let html = render_html("```\"><script>\n</code></pre><script>\n```", &[]);
+        assert_eq!(
+            html,
+            "<pre><code>&lt;/code&gt;&lt;/pre&gt;&lt;script&gt;</code></pre>"
+        );
+    }
+}
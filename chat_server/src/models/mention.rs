@@ -0,0 +1,231 @@
+use std::collections::BTreeSet;
+
+use chat_core::Message;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, Postgres, Transaction};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{models::markdown::find_mentions, AppError, AppState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mention {
+    /// `@here` or `@channel`, every member of the chat
+    All(&'static str),
+    User(i64),
+}
+
+#[derive(Debug, Clone, Default, Serialize, IntoParams, ToSchema, Deserialize)]
+pub struct ListMentions {
+    #[serde(default)]
+    pub last_id: Option<u64>,
+    #[serde(default)]
+    pub limit: u64,
+}
+
+impl AppState {
+    /// Messages mentioning the user in the chats they are still in, newest first.
+    pub async fn list_mentions(
+        &self,
+        input: ListMentions,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Vec<Message>, AppError> {
+        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+        let limit = match input.limit {
+            1..=100 => input.limit as _,
+            _ => 20i64,
+        };
+        let messages = sqlx::query_as(
+            r#"
+        SELECT m.id, m.chat_id, m.sender_id, m.content, m.files, m.created_at, m.edited_at,
+          m.deleted_at, m.parent_id, m.reply_count, m.last_reply_at, m.expires_at, m.reference,
+          m.content_html, message_reactions_json(m.id) AS reactions
+        FROM message_mentions mm
+        JOIN visible_messages m ON m.id = mm.message_id
+        JOIN chats c ON c.id = m.chat_id
+        JOIN chat_members cm ON cm.chat_id = m.chat_id AND cm.user_id = mm.user_id
+        WHERE mm.user_id = $1 AND c.ws_id = $2 AND mm.message_id < $3 AND m.deleted_at IS NULL
+        ORDER BY mm.message_id DESC
+        LIMIT $4
+        "#,
+        )
+        .bind(user_id as i64)
+        .bind(ws_id as i64)
+        .bind(last_id as i64)
+        .bind(limit)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(messages)
+    }
+}
+
+/// Store the members of the chat mentioned in the message, the sender is never mentioned.
+pub(crate) async fn save_mentions(
+    tx: &mut Transaction<'_, Postgres>,
+    message: &Message,
+    members: &[(i64, String)],
+) -> Result<Vec<i64>, AppError> {
+    if !message.content.contains('@') {
+        return Ok(vec![]);
+    }
+    let user_ids: Vec<_> = parse_mentions(&message.content, members)
+        .into_iter()
+        .filter(|id| *id != message.sender_id)
+        .collect();
+    if user_ids.is_empty() {
+        return Ok(user_ids);
+    }
+    sqlx::query(
+        r#"
+        INSERT INTO message_mentions (message_id, user_id)
+        SELECT $1, unnest($2::bigint[])
+        "#,
+    )
+    .bind(message.id)
+    .bind(&user_ids)
+    .execute(&mut **tx)
+    .await?;
+    Ok(user_ids)
+}
+
+pub(crate) async fn fetch_members(
+    conn: &mut PgConnection,
+    chat_id: i64,
+) -> Result<Vec<(i64, String)>, AppError> {
+    let members = sqlx::query_as(
+        r#"
+        SELECT u.id, u.username
+        FROM chat_members m
+        JOIN users u ON u.id = m.user_id
+        WHERE m.chat_id = $1
+        "#,
+    )
+    .bind(chat_id)
+    .fetch_all(conn)
+    .await?;
+    Ok(members)
+}
+
+/// Ids of the members mentioned as `@username` (case insensitive, usernames may contain
+/// spaces so the longest match wins), `@here` or `@channel`. There is no presence yet,
+/// so `@here` mentions every member like `@channel` does.
+///
+/// Only what the rendered message shows as a mention counts: not in code, urls or
+/// after a word like in email addresses.
+fn parse_mentions(content: &str, members: &[(i64, String)]) -> BTreeSet<i64> {
+    let mut ids = BTreeSet::new();
+    for mention in find_mentions(content, members) {
+        match mention {
+            Mention::All(_) => return members.iter().map(|(id, _)| *id).collect(),
+            Mention::User(id) => {
+                ids.insert(id);
+            }
+        }
+    }
+    ids
+}
+
+/// What the text after an `@` mentions, with the length of the matched name.
+pub(crate) fn match_mention(text: &str, members: &[(i64, String)]) -> Option<(Mention, usize)> {
+    if let Some(name) = ["here", "channel"]
+        .into_iter()
+        .find(|name| starts_with_name(text, name))
+    {
+        return Some((Mention::All(name), name.len()));
+    }
+    members
+        .iter()
+        .filter(|(_, name)| starts_with_name(text, name))
+        .max_by_key(|(_, name)| name.len())
+        .map(|(id, name)| (Mention::User(*id), name.len()))
+}
+
+/// Whether the text starts with the name followed by a word boundary.
+fn starts_with_name(text: &str, name: &str) -> bool {
+    if name.is_empty() {
+        return false;
+    }
+    match text.get(..name.len()) {
+        Some(prefix) if prefix.eq_ignore_ascii_case(name) => text[name.len()..]
+            .chars()
+            .next()
+            .is_none_or(|c| !c.is_alphanumeric() && c != '_'),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{chat::ListChats, message::CreateMessage};
+    use anyhow::Result;
+
+    #[test]
+    fn parse_mentions_should_work() {
+        let members = vec![
+            (1, "Tyr".to_string()),
+            (2, "Tyr Chen".to_string()),
+            (3, "Alice".to_string()),
+        ];
+        let ids: Vec<_> = parse_mentions("hi @tyr chen and @alice!", &members)
+            .into_iter()
+            .collect();
+        assert_eq!(ids, vec![2, 3]);
+        let ids: Vec<_> = parse_mentions("@Tyr, mail alice@acme.org", &members)
+            .into_iter()
+            .collect();
+        assert_eq!(ids, vec![1]);
+        assert!(parse_mentions("@Alicia @bob", &members).is_empty());
+        assert!(parse_mentions("mail ops@channel.io or bob@alice.dev", &members).is_empty());
+        let ids: Vec<_> = parse_mentions("(@alice) **@tyr**", &members)
+            .into_iter()
+            .collect();
+        assert_eq!(ids, vec![1, 3]);
+        assert_eq!(parse_mentions("ping @here", &members).len(), 3);
+        // pasted code doesn't notify anyone
+        let content = "```\necho @here\n```\nrun `notify @channel` @alice";
+        let ids: Vec<_> = parse_mentions(content, &members).into_iter().collect();
+        assert_eq!(ids, vec![3]);
+        assert_eq!(
+            match_mention("tyr chen, hi", &members),
+            Some((Mention::User(2), 8))
+        );
+        assert_eq!(
+            match_mention("channel!", &members),
+            Some((Mention::All("channel"), 7))
+        );
+    }
+
+    #[tokio::test]
+    async fn mentions_should_be_saved_and_listed() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // Daisy is not in the private channel
+        let input = CreateMessage {
+            content: "@alice chen @Daisy Chen please review".to_string(),
+            ..Default::default()
+        };
+        let message = state.create_message(input, 2, 1).await?;
+        let input = CreateMessage {
+            content: "@channel standup in 5".to_string(),
+            ..Default::default()
+        };
+        let all = state.create_message(input, 1, 3).await?;
+
+        let mentions = state.list_mentions(ListMentions::default(), 2, 1).await?;
+        let ids: Vec<_> = mentions.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![all.id, message.id]);
+        let mentions = state.list_mentions(ListMentions::default(), 5, 1).await?;
+        let ids: Vec<_> = mentions.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![all.id]);
+        // the sender doesn't mention themselves
+        assert!(state
+            .list_mentions(ListMentions::default(), 3, 1)
+            .await?
+            .is_empty());
+
+        let chats = state.fetch_chats(2, 1, ListChats::default()).await?;
+        let private = chats.iter().find(|c| c.chat.id == 2).unwrap();
+        assert_eq!(private.mention_count, 1);
+        Ok(())
+    }
+}
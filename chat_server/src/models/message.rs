@@ -1,23 +1,114 @@
 use std::str::FromStr;
 
-use chat_core::Message;
+use chat_core::{Message, MessageReference, ReferenceKind, User};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, types::Json};
 use utoipa::{IntoParams, ToSchema};
 
-use crate::{models::ChatFile, AppError, AppState};
+use crate::{
+    models::{
+        markdown::{normalize, render_html, MAX_CONTENT_LENGTH},
+        mention::{fetch_members, save_mentions},
+        permission::Action,
+        ChatFile,
+    },
+    AppError, AppState,
+};
 
-#[derive(Debug, Clone, Serialize, ToSchema, Deserialize)]
+const DEFAULT_PAGE_SIZE: u64 = 50;
+const MAX_PAGE_SIZE: u64 = 100;
+const MAX_CLIENT_ID_LENGTH: usize = 64;
+/// a year
+pub(crate) const MAX_MESSAGE_TTL_SECS: u64 = 60 * 60 * 24 * 365;
+
+#[derive(Debug, Clone, Default, Serialize, ToSchema, Deserialize)]
 pub struct CreateMessage {
     pub content: String,
     #[serde(default)]
     pub files: Vec<String>,
+    /// reply in the thread of this message
+    #[serde(default, alias = "parentId")]
+    pub parent_id: Option<u64>,
+    /// delete the message after this many seconds, the chat's default if not set
+    #[serde(default, alias = "ttlSecs")]
+    pub ttl_secs: Option<u64>,
+    /// nonce generated by the client, sending again with the same one returns the
+    /// original message instead of creating a duplicate
+    #[serde(default, alias = "clientId")]
+    pub client_id: Option<String>,
+    /// reply while quoting this message
+    #[serde(default, alias = "quoteId")]
+    pub quote_id: Option<u64>,
+    /// forward this message with its files, the content is an optional comment
+    #[serde(default, alias = "forwardId")]
+    pub forward_id: Option<u64>,
 }
-#[derive(Debug, Clone, Serialize, IntoParams, ToSchema, Deserialize)]
+/// A page of messages, newest first. Only one of `around_id`, `after_id` and `last_id` is
+/// used, in that order.
+#[derive(Debug, Clone, Default, Serialize, IntoParams, ToSchema, Deserialize)]
 pub struct ListMessages {
+    /// messages older than this one
     #[serde(default)]
     pub last_id: Option<u64>,
+    /// messages newer than this one
+    #[serde(default)]
+    pub after_id: Option<u64>,
+    /// this message with the messages around it, e.g. for a permalink
+    #[serde(default)]
+    pub around_id: Option<u64>,
+    /// only messages sent at or after this time
+    #[serde(default)]
+    pub since: Option<DateTime<Utc>>,
+    /// only messages sent before this time
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+    /// page size, 50 by default and 100 at most
     #[serde(default)]
     pub limit: u64,
+    /// leave thread replies out of the timeline
+    #[serde(default)]
+    pub hide_replies: bool,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessagePage {
+    /// newest first
+    pub messages: Vec<Message>,
+    /// older messages can be loaded with the last message id as `last_id`
+    pub has_more_before: bool,
+    /// newer messages can be loaded with the first message id as `after_id`
+    pub has_more_after: bool,
+}
+
+/// Messages older (exclusive) or newer (exclusive) than a message id.
+#[derive(Debug, Clone, Copy)]
+enum Range {
+    Before(i64),
+    After(i64),
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema, Deserialize)]
+pub struct Thread {
+    pub parent: Message,
+    /// newest first, paginated like the chat messages
+    pub replies: Vec<Message>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema, Deserialize)]
+pub struct UpdateMessage {
+    pub content: String,
+}
+
+/// A previous version of an edited message.
+#[derive(Debug, Clone, FromRow, Serialize, ToSchema, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageEdit {
+    pub id: i64,
+    pub message_id: i64,
+    pub content: String,
+    pub edited_at: DateTime<Utc>,
 }
 
 #[allow(dead_code)]
@@ -28,14 +119,222 @@ impl AppState {
         chat_id: u64,
         user_id: u64,
     ) -> Result<Message, AppError> {
-        if input.content.is_empty() {
+        let (message, _) = self.send_message(input, chat_id, user_id).await?;
+        Ok(message)
+    }
+
+    /// Create a message, or return the one the sender already created with the same
+    /// `client_id`. The bool is true if the message was created.
+    pub async fn send_message(
+        &self,
+        input: CreateMessage,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<(Message, bool), AppError> {
+        let reference = match (input.quote_id, input.forward_id) {
+            (Some(_), Some(_)) => {
+                return Err(AppError::CreateMessageError(
+                    "A message can't quote and forward at the same time".to_string(),
+                ))
+            }
+            (Some(id), None) => Some((id, ReferenceKind::Quote)),
+            (None, Some(id)) => Some((id, ReferenceKind::Forward)),
+            (None, None) => None,
+        };
+        let is_forward = input.forward_id.is_some();
+        let content =
+            normalize_content(&input.content, is_forward).map_err(AppError::CreateMessageError)?;
+        if input
+            .ttl_secs
+            .is_some_and(|ttl| ttl == 0 || ttl > MAX_MESSAGE_TTL_SECS)
+        {
+            return Err(AppError::CreateMessageError(format!(
+                "Time to live must be between 1 and {MAX_MESSAGE_TTL_SECS} seconds"
+            )));
+        }
+        if input
+            .client_id
+            .as_ref()
+            .is_some_and(|id| id.is_empty() || id.len() > MAX_CLIENT_ID_LENGTH)
+        {
+            return Err(AppError::CreateMessageError(format!(
+                "Client id must be between 1 and {MAX_CLIENT_ID_LENGTH} bytes"
+            )));
+        }
+        if let Some(message) = self
+            .find_sent_message(chat_id, user_id, input.client_id.as_deref())
+            .await?
+        {
+            return Ok((message, false));
+        }
+        self.verify_files(&input.files)?;
+        if let Some(parent_id) = input.parent_id {
+            self.verify_thread_parent(chat_id, parent_id).await?;
+        }
+        let reference = match reference {
+            Some((id, kind)) => Some(self.reference_message(chat_id, user_id, id, kind).await?),
+            None => None,
+        };
+        // forwarded files are shared with the original, they were uploaded already
+        let mut files = input.files;
+        if let Some(reference) = reference.as_ref().filter(|_| is_forward) {
+            for file in &reference.files {
+                if !files.contains(file) {
+                    files.push(file.clone());
+                }
+            }
+        }
+        let mut tx = self.pg_pool.begin().await?;
+        // the chat can't be archived until the message is in, inserting the message
+        // locks the chat the same way to update its last message
+        let archived: bool = sqlx::query_scalar(
+            "SELECT archived_at IS NOT NULL FROM chats WHERE id = $1 FOR NO KEY UPDATE",
+        )
+        .bind(chat_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))?;
+        if archived {
+            return Err(AppError::CreateMessageError("Chat is archived".to_string()));
+        }
+        let members = if content.contains('@') {
+            fetch_members(&mut tx, chat_id as _).await?
+        } else {
+            vec![]
+        };
+        let content_html = render_html(&content, &members);
+        let message: Option<Message> = sqlx::query_as(
+            r#"
+          INSERT INTO messages (chat_id, sender_id, content, files, parent_id, expires_at, client_id,
+            reference, content_html)
+          SELECT $1, $2, $3, $4, $5,
+            now() + make_interval(secs => COALESCE($6, message_ttl_secs)), $7, $8, $9
+          FROM chats
+          WHERE id = $1
+          ON CONFLICT (sender_id, client_id) WHERE client_id IS NOT NULL DO NOTHING
+          RETURNING id, chat_id, sender_id, content, files, created_at, edited_at, deleted_at,
+            parent_id, reply_count, last_reply_at, expires_at, client_id, content_html,
+            COALESCE(reference, 'null') AS reference
+          "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(content)
+        .bind(&files)
+        .bind(input.parent_id.map(|v| v as i64))
+        .bind(input.ttl_secs.map(|v| v as f64))
+        .bind(&input.client_id)
+        .bind(reference.map(Json))
+        .bind(content_html)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(message) = message else {
+            tx.rollback().await?;
+            // only a conflicting client id skips the insert, a concurrent retry won it
+            let client_id = input.client_id.unwrap_or_default();
+            return match self
+                .find_sent_message(chat_id, user_id, Some(&client_id))
+                .await?
+            {
+                Some(message) => Ok((message, false)),
+                None => Err(AppError::CreateMessageError(format!(
+                    "Client id {client_id} is already used"
+                ))),
+            };
+        };
+        save_mentions(&mut tx, &message, &members).await?;
+        tx.commit().await?;
+        Ok((message, true))
+    }
+
+    /// Snapshot of a message the user can read, to be quoted or forwarded into a chat of
+    /// the same workspace. Forwarding a forward refers to the original message.
+    async fn reference_message(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+        message_id: u64,
+        kind: ReferenceKind,
+    ) -> Result<MessageReference, AppError> {
+        let message: Message = sqlx::query_as(
+            r#"
+          SELECT m.id, m.chat_id, m.sender_id, m.content, m.files, m.created_at, m.edited_at,
+            m.deleted_at, m.parent_id, m.reply_count, m.last_reply_at, m.expires_at, m.reference,
+            m.content_html
+          FROM visible_messages m
+          JOIN chats c ON c.id = m.chat_id
+          JOIN chat_members cm ON cm.chat_id = m.chat_id AND cm.user_id = $2
+          WHERE m.id = $1 AND m.deleted_at IS NULL
+            AND c.ws_id = (SELECT ws_id FROM chats WHERE id = $3)
+          "#,
+        )
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&self.pg_pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("message id {message_id}")))?;
+        // a copy would outlive the original
+        if message.expires_at.is_some() {
             return Err(AppError::CreateMessageError(
-                "Content cannot be empty".to_string(),
+                "Self-destructing messages can't be quoted or forwarded".to_string(),
             ));
         }
-        let base_dir = &self.config.server.base_dir;
+        match message.reference {
+            Some(reference)
+                if kind == ReferenceKind::Forward && reference.kind == ReferenceKind::Forward =>
+            {
+                Ok(reference)
+            }
+            _ => Ok(MessageReference {
+                kind,
+                message_id: message.id,
+                chat_id: message.chat_id,
+                sender_id: message.sender_id,
+                content: message.content,
+                content_html: message.content_html,
+                files: message.files,
+                created_at: message.created_at,
+            }),
+        }
+    }
+
+    /// The message the sender already sent with this client id.
+    async fn find_sent_message(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+        client_id: Option<&str>,
+    ) -> Result<Option<Message>, AppError> {
+        let Some(client_id) = client_id else {
+            return Ok(None);
+        };
+        let message: Option<Message> = sqlx::query_as(
+            r#"
+          SELECT m.id, m.chat_id, m.sender_id, m.content, m.files, m.created_at, m.edited_at,
+            m.deleted_at, m.parent_id, m.reply_count, m.last_reply_at, m.expires_at, m.reference,
+            m.content_html, c.client_id
+          FROM visible_messages m
+          JOIN messages c ON c.id = m.id
+          WHERE c.sender_id = $1 AND c.client_id = $2
+          "#,
+        )
+        .bind(user_id as i64)
+        .bind(client_id)
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        match message {
+            Some(message) if message.chat_id != chat_id as i64 => Err(
+                AppError::CreateMessageError(format!("Client id {client_id} is already used")),
+            ),
+            message => Ok(message),
+        }
+    }
 
-        for s in &input.files {
+    /// Files attached to a message must have been uploaded.
+    pub(crate) fn verify_files(&self, files: &[String]) -> Result<(), AppError> {
+        let base_dir = &self.config.server.base_dir;
+        for s in files {
             let file = ChatFile::from_str(s)?;
             if !file.path(base_dir).exists() {
                 return Err(AppError::CreateMessageError(format!(
@@ -44,61 +343,368 @@ impl AppState {
                 )));
             }
         }
-        let message: Message = sqlx::query_as(
+        Ok(())
+    }
+
+    /// Edit the content of a message, only the sender can do that. The previous
+    /// content goes to the edit history.
+    pub async fn update_message(
+        &self,
+        input: UpdateMessage,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<Message, AppError> {
+        let new_content =
+            normalize_content(&input.content, false).map_err(AppError::UpdateMessageError)?;
+        let mut tx = self.pg_pool.begin().await?;
+        let (sender_id, content, archived_at): (i64, String, Option<DateTime<Utc>>) =
+            sqlx::query_as(
+                r#"
+            SELECT m.sender_id, m.content, c.archived_at
+            FROM messages m
+            JOIN chats c ON c.id = m.chat_id
+            WHERE m.id = $1 AND m.chat_id = $2 AND m.deleted_at IS NULL
+              AND (m.expires_at IS NULL OR m.expires_at > now())
+            FOR UPDATE OF m
+            "#,
+            )
+            .bind(message_id as i64)
+            .bind(chat_id as i64)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("message id {message_id}")))?;
+        if sender_id != user_id as i64 {
+            return Err(AppError::Forbidden(
+                "Only the sender can edit a message".to_string(),
+            ));
+        }
+        if archived_at.is_some() {
+            return Err(AppError::UpdateMessageError("Chat is archived".to_string()));
+        }
+
+        sqlx::query("INSERT INTO message_edits (message_id, content) VALUES ($1, $2)")
+            .bind(message_id as i64)
+            .bind(content)
+            .execute(&mut *tx)
+            .await?;
+        let members = if new_content.contains('@') {
+            fetch_members(&mut tx, chat_id as _).await?
+        } else {
+            vec![]
+        };
+        let content_html = render_html(&new_content, &members);
+        let message = sqlx::query_as(
             r#"
-          INSERT INTO messages (chat_id, sender_id, content, files)
-          VALUES ($1, $2, $3, $4)
-          RETURNING id, chat_id, sender_id, content, files, created_at
-          "#,
+            UPDATE messages
+            SET content = $2, content_html = $3, edited_at = now()
+            WHERE id = $1
+            RETURNING id, chat_id, sender_id, content, files, created_at, edited_at, deleted_at,
+              parent_id, reply_count, last_reply_at, expires_at, content_html,
+              COALESCE(reference, 'null') AS reference, message_reactions_json(id) AS reactions
+            "#,
         )
-        .bind(chat_id as i64)
-        .bind(user_id as i64)
-        .bind(input.content)
-        .bind(&input.files)
-        .fetch_one(&self.pg_pool)
+        .bind(message_id as i64)
+        .bind(new_content)
+        .bind(content_html)
+        .fetch_one(&mut *tx)
         .await?;
+        tx.commit().await?;
         Ok(message)
     }
 
+    /// Replace the message with a tombstone. Senders can delete their own messages,
+    /// chat and workspace admins anyone's.
+    pub async fn delete_message(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user: &User,
+    ) -> Result<(), AppError> {
+        let mut tx = self.pg_pool.begin().await?;
+        let sender_id: i64 = sqlx::query_scalar(
+            r#"
+            SELECT sender_id
+            FROM messages
+            WHERE id = $1 AND chat_id = $2 AND deleted_at IS NULL
+            FOR UPDATE
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("message id {message_id}")))?;
+        if sender_id != user.id {
+            self.fetch_and_verify_chat(chat_id, user, Action::ModerateMessages)
+                .await?;
+        }
+
+        // the edit history would still have the content
+        sqlx::query("DELETE FROM message_edits WHERE message_id = $1")
+            .bind(message_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM message_reactions WHERE message_id = $1")
+            .bind(message_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM chat_pins WHERE message_id = $1")
+            .bind(message_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            UPDATE messages
+            SET content = '', content_html = '', files = '{}', reference = NULL,
+              deleted_at = now()
+            WHERE id = $1
+            "#,
+        )
+        .bind(message_id as i64)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Previous versions of a message, oldest first.
+    pub async fn list_message_edits(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+    ) -> Result<Vec<MessageEdit>, AppError> {
+        let edits = sqlx::query_as(
+            r#"
+            SELECT e.id, e.message_id, e.content, e.edited_at
+            FROM message_edits e
+            JOIN visible_messages m ON m.id = e.message_id
+            WHERE e.message_id = $1 AND m.chat_id = $2 AND m.deleted_at IS NULL
+            ORDER BY e.id
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(edits)
+    }
+
     pub async fn list_messages(
         &self,
         input: ListMessages,
         chat_id: u64,
+    ) -> Result<MessagePage, AppError> {
+        let limit = match input.limit {
+            0 => DEFAULT_PAGE_SIZE,
+            1..=MAX_PAGE_SIZE => input.limit,
+            _ => MAX_PAGE_SIZE,
+        } as i64;
+        let page = if let Some(id) = input.around_id {
+            // the anchor is part of the older half
+            let id = id as i64;
+            let mut before = self
+                .fetch_message_range(&input, chat_id, Range::Before(id + 1), limit - limit / 2)
+                .await?;
+            let after = self
+                .fetch_message_range(&input, chat_id, Range::After(id), limit / 2)
+                .await?;
+            let has_more_before = before.len() > (limit - limit / 2) as usize;
+            let has_more_after = after.len() > (limit / 2) as usize;
+            before.truncate((limit - limit / 2) as usize);
+            let messages = after
+                .into_iter()
+                .take((limit / 2) as usize)
+                .rev()
+                .chain(before)
+                .collect();
+            MessagePage {
+                messages,
+                has_more_before,
+                has_more_after,
+            }
+        } else if let Some(id) = input.after_id {
+            let mut messages = self
+                .fetch_message_range(&input, chat_id, Range::After(id as _), limit)
+                .await?;
+            let has_more_after = messages.len() > limit as usize;
+            messages.truncate(limit as usize);
+            messages.reverse();
+            let has_more_before = !self
+                .fetch_message_range(&input, chat_id, Range::Before(id as i64 + 1), 0)
+                .await?
+                .is_empty();
+            MessagePage {
+                messages,
+                has_more_before,
+                has_more_after,
+            }
+        } else {
+            let last_id = input.last_id.map(|id| id as i64).unwrap_or(i64::MAX);
+            let mut messages = self
+                .fetch_message_range(&input, chat_id, Range::Before(last_id), limit)
+                .await?;
+            let has_more_before = messages.len() > limit as usize;
+            messages.truncate(limit as usize);
+            let has_more_after = match input.last_id {
+                Some(id) => !self
+                    .fetch_message_range(&input, chat_id, Range::After(id as i64 - 1), 0)
+                    .await?
+                    .is_empty(),
+                None => false,
+            };
+            MessagePage {
+                messages,
+                has_more_before,
+                has_more_after,
+            }
+        };
+        Ok(page)
+    }
+
+    /// Up to `limit + 1` messages of the range, the extra one tells if there are more.
+    /// Older messages come newest first, newer messages oldest first.
+    async fn fetch_message_range(
+        &self,
+        input: &ListMessages,
+        chat_id: u64,
+        range: Range,
+        limit: i64,
     ) -> Result<Vec<Message>, AppError> {
+        let (sql, id) = match range {
+            Range::Before(id) => (
+                r#"
+        SELECT id, chat_id, sender_id, content, files, created_at, edited_at, deleted_at,
+          parent_id, reply_count, last_reply_at, expires_at, reference, content_html,
+          message_reactions_json(id) AS reactions
+        FROM visible_messages
+        WHERE chat_id = $1 AND id < $2 AND NOT ($4 AND parent_id IS NOT NULL)
+          AND ($5::timestamptz IS NULL OR created_at >= $5)
+          AND ($6::timestamptz IS NULL OR created_at < $6)
+        ORDER BY id DESC
+        LIMIT $3
+        "#,
+                id,
+            ),
+            Range::After(id) => (
+                r#"
+        SELECT id, chat_id, sender_id, content, files, created_at, edited_at, deleted_at,
+          parent_id, reply_count, last_reply_at, expires_at, reference, content_html,
+          message_reactions_json(id) AS reactions
+        FROM visible_messages
+        WHERE chat_id = $1 AND id > $2 AND NOT ($4 AND parent_id IS NOT NULL)
+          AND ($5::timestamptz IS NULL OR created_at >= $5)
+          AND ($6::timestamptz IS NULL OR created_at < $6)
+        ORDER BY id ASC
+        LIMIT $3
+        "#,
+                id,
+            ),
+        };
+        let messages = sqlx::query_as(sql)
+            .bind(chat_id as i64)
+            .bind(id)
+            .bind(limit + 1)
+            .bind(input.hide_replies)
+            .bind(input.since)
+            .bind(input.until)
+            .fetch_all(&self.pg_pool)
+            .await?;
+        Ok(messages)
+    }
+
+    /// The message with a page of its replies.
+    pub async fn fetch_thread(
+        &self,
+        input: ListMessages,
+        chat_id: u64,
+        message_id: u64,
+    ) -> Result<Thread, AppError> {
+        let parent = self.get_message_by_id(chat_id, message_id).await?;
         let last_id = input.last_id.unwrap_or(i64::MAX as _);
         let limit = match input.limit {
-            0 => i64::MAX,
             1..=100 => input.limit as _,
             _ => 100,
         };
-        let messages = sqlx::query_as(
+        let replies = sqlx::query_as(
             r#"
-        SELECT id, chat_id, sender_id, content, files, created_at
-        FROM messages
-        WHERE chat_id = $1 AND id < $2
+        SELECT id, chat_id, sender_id, content, files, created_at, edited_at, deleted_at,
+          parent_id, reply_count, last_reply_at, expires_at, reference, content_html,
+          message_reactions_json(id) AS reactions
+        FROM visible_messages
+        WHERE parent_id = $1 AND id < $2
         ORDER BY id DESC
         LIMIT $3
         "#,
         )
-        .bind(chat_id as i64)
+        .bind(parent.id)
         .bind(last_id as i64)
         .bind(limit)
         .fetch_all(&self.pg_pool)
         .await?;
-        Ok(messages)
+        Ok(Thread { parent, replies })
+    }
+
+    pub async fn get_message_by_id(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+    ) -> Result<Message, AppError> {
+        let message = sqlx::query_as(
+            r#"
+        SELECT id, chat_id, sender_id, content, files, created_at, edited_at, deleted_at,
+          parent_id, reply_count, last_reply_at, expires_at, reference, content_html,
+          message_reactions_json(id) AS reactions
+        FROM visible_messages
+        WHERE id = $1 AND chat_id = $2
+        "#,
+        )
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&self.pg_pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("message id {message_id}")))?;
+        Ok(message)
+    }
+
+    /// Replies go to top level messages of the same chat that are not deleted.
+    async fn verify_thread_parent(&self, chat_id: u64, parent_id: u64) -> Result<(), AppError> {
+        let parent = self.get_message_by_id(chat_id, parent_id).await?;
+        if parent.parent_id.is_some() || parent.deleted_at.is_some() {
+            return Err(AppError::CreateMessageError(format!(
+                "Can't reply to message {parent_id}"
+            )));
+        }
+        Ok(())
     }
 }
 
+/// The normalized content, or why it can't be sent.
+pub(crate) fn normalize_content(content: &str, allow_empty: bool) -> Result<String, String> {
+    let content = normalize(content);
+    if content.is_empty() && !allow_empty {
+        return Err("Content cannot be empty".to_string());
+    }
+    if content.chars().count() > MAX_CONTENT_LENGTH {
+        return Err(format!(
+            "Content cannot be longer than {MAX_CONTENT_LENGTH} characters"
+        ));
+    }
+    Ok(content)
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
+    use chat_core::ReferenceKind;
 
     use crate::{
         models::{
-            message::{CreateMessage, ListMessages},
+            markdown::MAX_CONTENT_LENGTH,
+            message::{CreateMessage, ListMessages, UpdateMessage},
             ChatFile,
         },
-        AppState,
+        AppError, AppState,
     };
     #[tokio::test]
     async fn create_message_should_work() -> Result<()> {
@@ -106,6 +712,7 @@ mod tests {
         let input = CreateMessage {
             content: "hello world".to_string(),
             files: vec![],
+            ..Default::default()
         };
         let message = state
             .create_message(input, 1, 1)
@@ -115,6 +722,7 @@ mod tests {
         let input = CreateMessage {
             content: "test".to_string(),
             files: vec!["1".to_string()],
+            ..Default::default()
         };
 
         let err = state.create_message(input, 1, 1).await.unwrap_err();
@@ -123,6 +731,7 @@ mod tests {
         let input = CreateMessage {
             content: "test".to_string(),
             files: vec![url],
+            ..Default::default()
         };
         let message = state
             .create_message(input, 1, 1)
@@ -132,6 +741,339 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn message_content_should_be_rendered() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "**ship it** @Alice Chen  \r\n<script>alert(1)</script>\n\n\n".to_string(),
+            ..Default::default()
+        };
+        let message = state.create_message(input, 1, 1).await?;
+        assert_eq!(
+            message.content,
+            "**ship it** @Alice Chen\n<script>alert(1)</script>"
+        );
+        assert_eq!(
+            message.content_html,
+            "<p><strong>ship it</strong> <span class=\"mention\" data-user-id=\"2\">@Alice Chen</span><br>&lt;script&gt;alert(1)&lt;/script&gt;</p>"
+        );
+        let input = UpdateMessage {
+            content: "`fixed`".to_string(),
+        };
+        let message = state.update_message(input, 1, message.id as _, 1).await?;
+        assert_eq!(message.content_html, "<p><code>fixed</code></p>");
+        let message = state.get_message_by_id(1, message.id as _).await?;
+        assert_eq!(message.content_html, "<p><code>fixed</code></p>");
+
+        let input = CreateMessage {
+            content: " \n\n ".to_string(),
+            ..Default::default()
+        };
+        let ret = state.create_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn message_of_max_length_should_be_sent() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // escaping and multibyte characters make the html much longer than the content
+        let content = format!(
+            "@channel {}",
+            "<界".repeat((MAX_CONTENT_LENGTH - "@channel ".len()) / 2)
+        );
+        let input = CreateMessage {
+            content: content.clone(),
+            ..Default::default()
+        };
+        let message = state.create_message(input, 1, 1).await?;
+        assert_eq!(message.content, content);
+
+        let input = CreateMessage {
+            content: content.clone(),
+            parent_id: Some(message.id as _),
+            ..Default::default()
+        };
+        state.create_message(input, 1, 2).await?;
+        let input = UpdateMessage {
+            content: format!("{content}!"),
+        };
+        state.update_message(input, 1, message.id as _, 1).await?;
+        let ivena = state.find_user_by_id(1).await?.expect("user should exist");
+        state.delete_message(1, message.id as _, &ivena).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn quote_and_forward_should_keep_snapshot() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let url = upload_dummy_file(&state)?;
+        let input = CreateMessage {
+            content: "release notes".to_string(),
+            files: vec![url.clone()],
+            ..Default::default()
+        };
+        let original = state.create_message(input, 1, 2).await?;
+
+        // Alice forwards from general to the private channel without a comment
+        let input = CreateMessage {
+            content: "".to_string(),
+            forward_id: Some(original.id as _),
+            ..Default::default()
+        };
+        let forward = state.create_message(input, 2, 2).await?;
+        assert_eq!(forward.files, vec![url.clone()]);
+        let reference = forward.reference.clone().expect("reference should exist");
+        assert_eq!(reference.kind, ReferenceKind::Forward);
+        assert_eq!(reference.message_id, original.id);
+        assert_eq!(reference.content, "release notes");
+
+        // forwarding the forward refers to the original
+        let input = CreateMessage {
+            content: "fyi".to_string(),
+            forward_id: Some(forward.id as _),
+            ..Default::default()
+        };
+        let message = state.create_message(input, 1, 3).await?;
+        assert_eq!(message.reference, Some(reference));
+
+        // the snapshot survives deletion of the original
+        let alice = state.find_user_by_id(2).await?.expect("user should exist");
+        state.delete_message(1, original.id as _, &alice).await?;
+        let input = CreateMessage {
+            content: "agreed".to_string(),
+            quote_id: Some(forward.id as _),
+            ..Default::default()
+        };
+        let quote = state.create_message(input, 2, 3).await?;
+        let reference = quote.reference.expect("reference should exist");
+        assert_eq!(reference.kind, ReferenceKind::Quote);
+        assert_eq!(reference.message_id, forward.id);
+        assert!(quote.files.is_empty());
+        let forward = state.get_message_by_id(2, forward.id as _).await?;
+        assert_eq!(
+            forward.reference.map(|r| r.content),
+            Some("release notes".to_string())
+        );
+
+        // Alice is not a member of the group chat
+        let group = state
+            .create_message(
+                CreateMessage {
+                    content: "secret".to_string(),
+                    ..Default::default()
+                },
+                4,
+                3,
+            )
+            .await?;
+        let input = CreateMessage {
+            content: "".to_string(),
+            forward_id: Some(group.id as _),
+            ..Default::default()
+        };
+        let ret = state.create_message(input, 1, 2).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        let input = CreateMessage {
+            content: "".to_string(),
+            quote_id: Some(1),
+            ..Default::default()
+        };
+        let ret = state.create_message(input, 1, 2).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn quote_of_max_length_message_should_be_sent() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let content = "<界".repeat(MAX_CONTENT_LENGTH / 2);
+        let input = CreateMessage {
+            content: content.clone(),
+            ..Default::default()
+        };
+        let original = state.create_message(input, 1, 2).await?;
+
+        // the reply carries its own content next to the snapshot of the original
+        let input = CreateMessage {
+            content: content.clone(),
+            quote_id: Some(original.id as _),
+            ..Default::default()
+        };
+        let quote = state.create_message(input, 1, 3).await?;
+        let reference = quote.reference.expect("reference should exist");
+        assert_eq!(reference.content, content);
+        let input = CreateMessage {
+            content: content.clone(),
+            forward_id: Some(quote.id as _),
+            ..Default::default()
+        };
+        let forward = state.create_message(input, 2, 2).await?;
+        assert_eq!(forward.reference.map(|r| r.message_id), Some(quote.id));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_message_with_client_id_should_be_idempotent() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            client_id: Some("7f3c2a".to_string()),
+            ..Default::default()
+        };
+        let (message, created) = state.send_message(input.clone(), 1, 1).await?;
+        assert!(created);
+        assert_eq!(message.client_id.as_deref(), Some("7f3c2a"));
+        let (retried, created) = state.send_message(input.clone(), 1, 1).await?;
+        assert!(!created);
+        assert_eq!(retried, message);
+
+        // the nonce is unique per sender
+        let (other, created) = state.send_message(input.clone(), 1, 2).await?;
+        assert!(created);
+        assert_ne!(other.id, message.id);
+        let ret = state.send_message(input, 2, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            client_id: Some("x".repeat(65)),
+            ..Default::default()
+        };
+        let ret = state.send_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            ..Default::default()
+        };
+        let ret = state.send_message(input, 100, 1).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        // a retry still finds the message after the chat was archived
+        let mut chat = state.get_chat_by_id(1).await?.expect("chat should exist");
+        state.set_chat_archived(&mut chat, true).await?;
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            client_id: Some("7f3c2a".to_string()),
+            ..Default::default()
+        };
+        let (retried, created) = state.send_message(input, 1, 1).await?;
+        assert!(!created);
+        assert_eq!(retried.id, message.id);
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            client_id: Some("9b1d4e".to_string()),
+            ..Default::default()
+        };
+        let ret = state.send_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(ref e)) if e == "Chat is archived"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_message_should_keep_history() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = UpdateMessage {
+            content: "Hello, everyone!".to_string(),
+        };
+        // message 2 was sent by Alice
+        let ret = state.update_message(input.clone(), 1, 2, 1).await;
+        assert!(matches!(ret, Err(AppError::Forbidden(_))));
+        let ret = state.update_message(input.clone(), 2, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        let message = state.update_message(input, 1, 1, 1).await?;
+        assert_eq!(message.content, "Hello, everyone!");
+        assert!(message.edited_at.is_some());
+        let edits = state.list_message_edits(1, 1).await?;
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].content, "Hello, world!");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_message_should_leave_tombstone() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let alice = state.find_user_by_id(2).await?.expect("user should exist");
+        let ivena = state.find_user_by_id(1).await?.expect("user should exist");
+        // message 1 was sent by Ivena, Alice is no admin
+        let ret = state.delete_message(1, 1, &alice).await;
+        assert!(matches!(ret, Err(AppError::Forbidden(_))));
+        state.delete_message(1, 2, &alice).await?;
+        // Ivena owns the workspace
+        state.delete_message(1, 3, &ivena).await?;
+        let ret = state.delete_message(1, 3, &ivena).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        let input = ListMessages {
+            last_id: Some(4),
+            limit: 10,
+            ..Default::default()
+        };
+        let messages = state.list_messages(input, 1).await?.messages;
+        assert_eq!(messages.len(), 3);
+        assert!(messages[0].deleted_at.is_some());
+        assert!(messages[0].content.is_empty());
+        assert!(messages[1].deleted_at.is_some());
+        assert!(messages[2].deleted_at.is_none());
+        let input = UpdateMessage {
+            content: "edited".to_string(),
+        };
+        let ret = state.update_message(input, 1, 2, 2).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn thread_replies_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "reply".to_string(),
+            parent_id: Some(1),
+            ..Default::default()
+        };
+        let reply = state.create_message(input.clone(), 1, 2).await?;
+        assert_eq!(reply.parent_id, Some(1));
+        state.create_message(input, 1, 3).await?;
+        // threads are one level deep
+        let input = CreateMessage {
+            content: "reply".to_string(),
+            parent_id: Some(reply.id as _),
+            ..Default::default()
+        };
+        let ret = state.create_message(input, 1, 2).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+
+        let thread = state.fetch_thread(ListMessages::default(), 1, 1).await?;
+        assert_eq!(thread.parent.reply_count, 2);
+        assert!(thread.parent.last_reply_at.is_some());
+        assert_eq!(thread.replies.len(), 2);
+        assert_eq!(thread.replies[1].id, reply.id);
+
+        // deleted replies stay in the thread as tombstones but are not counted
+        let bob = state.find_user_by_id(3).await?.expect("user should exist");
+        state
+            .delete_message(1, thread.replies[0].id as _, &bob)
+            .await?;
+        let parent = state.get_message_by_id(1, 1).await?;
+        assert_eq!(parent.reply_count, 1);
+        assert_eq!(parent.last_reply_at, Some(reply.created_at));
+
+        let input = ListMessages {
+            hide_replies: true,
+            ..Default::default()
+        };
+        assert_eq!(state.list_messages(input, 1).await?.messages.len(), 10);
+        assert_eq!(
+            state
+                .list_messages(ListMessages::default(), 1)
+                .await?
+                .messages
+                .len(),
+            12
+        );
+        Ok(())
+    }
+
     fn upload_dummy_file(state: &AppState) -> Result<String> {
         let file = ChatFile::new(1, "test.txt", "test".as_bytes());
         let path = file.path(&state.config.server.base_dir);
@@ -146,18 +1088,74 @@ mod tests {
         let input = ListMessages {
             last_id: None,
             limit: 6,
+            ..Default::default()
         };
-        let messages = state.list_messages(input, 1).await?;
-
-        assert_eq!(messages.len(), 6,);
+        let page = state.list_messages(input, 1).await?;
+        assert_eq!(page.messages.len(), 6);
+        assert!(page.has_more_before);
+        assert!(!page.has_more_after);
 
-        let last_id = messages.last().expect("last message").id;
+        let last_id = page.messages.last().expect("last message").id;
         let input = ListMessages {
             last_id: Some(last_id as _),
             limit: 6,
+            ..Default::default()
+        };
+        let page = state.list_messages(input, 1).await?;
+        assert_eq!(page.messages.len(), 4);
+        assert!(!page.has_more_before);
+        assert!(page.has_more_after);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_messages_after_and_around_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = ListMessages {
+            after_id: Some(2),
+            limit: 3,
+            ..Default::default()
+        };
+        let page = state.list_messages(input, 1).await?;
+        let ids: Vec<_> = page.messages.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![5, 4, 3]);
+        assert!(page.has_more_before);
+        assert!(page.has_more_after);
+
+        let input = ListMessages {
+            around_id: Some(5),
+            limit: 4,
+            ..Default::default()
+        };
+        let page = state.list_messages(input, 1).await?;
+        let ids: Vec<_> = page.messages.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![7, 6, 5, 4]);
+        assert!(page.has_more_before);
+        assert!(page.has_more_after);
+
+        let input = ListMessages {
+            around_id: Some(9),
+            limit: 6,
+            ..Default::default()
+        };
+        let page = state.list_messages(input, 1).await?;
+        let ids: Vec<_> = page.messages.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![10, 9, 8, 7]);
+        assert!(!page.has_more_after);
+
+        // all fixture messages were sent in the same transaction
+        let created_at = page.messages[0].created_at;
+        let input = ListMessages {
+            until: Some(created_at),
+            ..Default::default()
+        };
+        assert!(state.list_messages(input, 1).await?.messages.is_empty());
+        let input = ListMessages {
+            since: Some(created_at),
+            limit: 1000,
+            ..Default::default()
         };
-        let messages = state.list_messages(input, 1).await?;
-        assert_eq!(messages.len(), 4);
+        assert_eq!(state.list_messages(input, 1).await?.messages.len(), 10);
         Ok(())
     }
 }
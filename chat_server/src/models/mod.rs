@@ -1,6 +1,16 @@
 pub(crate) mod chat;
+pub(crate) mod expiry;
 pub(crate) mod file;
+pub(crate) mod markdown;
+pub(crate) mod mention;
 pub(crate) mod message;
+pub(crate) mod permission;
+pub(crate) mod pin;
+pub(crate) mod reaction;
+pub(crate) mod read;
+pub(crate) mod scheduled;
+pub(crate) mod search;
+pub(crate) mod session;
 pub(crate) mod user;
 pub(crate) mod workspace;
 use serde::{Deserialize, Serialize};
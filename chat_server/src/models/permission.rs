@@ -0,0 +1,189 @@
+use chat_core::{Chat, ChatRole, User, WorkspaceRole};
+
+use crate::{AppError, AppState};
+
+/// Actions that need more than being a member of the workspace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// invites and member roles
+    ManageWorkspace,
+    CreateChannel,
+    /// browse and join public channels
+    JoinChannel,
+    UpdateChat,
+    ArchiveChat,
+    /// delete messages of other members
+    ModerateMessages,
+    /// delete a chat with its history
+    PurgeChat,
+}
+
+impl AppState {
+    pub async fn workspace_role(
+        &self,
+        ws_id: i64,
+        user_id: i64,
+    ) -> Result<Option<WorkspaceRole>, AppError> {
+        let role = sqlx::query_scalar(
+            r#"
+            SELECT role
+            FROM workspace_members
+            WHERE ws_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(ws_id)
+        .bind(user_id)
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        Ok(role)
+    }
+
+    pub async fn chat_role(
+        &self,
+        chat_id: i64,
+        user_id: i64,
+    ) -> Result<Option<ChatRole>, AppError> {
+        let role = sqlx::query_scalar(
+            r#"
+            SELECT role
+            FROM chat_members
+            WHERE chat_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(chat_id)
+        .bind(user_id)
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        Ok(role)
+    }
+
+    /// Check the user may perform a workspace level action.
+    pub async fn authorize(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+        action: Action,
+    ) -> Result<WorkspaceRole, AppError> {
+        let role = self.member_role(ws_id, user_id).await?;
+        if !is_allowed(role, action, None) {
+            return Err(forbidden(role, action));
+        }
+        Ok(role)
+    }
+
+    /// Check the user may perform the action on the chat.
+    pub async fn authorize_chat(
+        &self,
+        user: &User,
+        chat: &Chat,
+        action: Action,
+    ) -> Result<WorkspaceRole, AppError> {
+        if chat.ws_id != user.ws_id {
+            return Err(AppError::NotFound(format!("chat id {}", chat.id)));
+        }
+        let role = self.member_role(user.ws_id as _, user.id as _).await?;
+        let chat_role = self.chat_role(chat.id, user.id).await?;
+        if !is_allowed(role, action, chat_role) {
+            return Err(forbidden(role, action));
+        }
+        Ok(role)
+    }
+
+    async fn member_role(&self, ws_id: u64, user_id: u64) -> Result<WorkspaceRole, AppError> {
+        self.workspace_role(ws_id as _, user_id as _)
+            .await?
+            .ok_or_else(|| AppError::Forbidden("You are not a member of the workspace".to_string()))
+    }
+}
+
+fn is_allowed(role: WorkspaceRole, action: Action, chat_role: Option<ChatRole>) -> bool {
+    match action {
+        Action::ManageWorkspace | Action::PurgeChat => role.is_admin(),
+        Action::CreateChannel | Action::JoinChannel => role != WorkspaceRole::Guest,
+        Action::UpdateChat => {
+            role.is_admin() || (chat_role.is_some() && role != WorkspaceRole::Guest)
+        }
+        Action::ArchiveChat | Action::ModerateMessages => {
+            role.is_admin() || chat_role.is_some_and(|r| r.is_admin())
+        }
+    }
+}
+
+fn forbidden(role: WorkspaceRole, action: Action) -> AppError {
+    AppError::Forbidden(format!("{:?} is not allowed to {:?}", role, action))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn is_allowed_should_follow_roles() {
+        use WorkspaceRole::*;
+        assert!(is_allowed(Admin, Action::ArchiveChat, None));
+        assert!(!is_allowed(
+            Member,
+            Action::ArchiveChat,
+            Some(ChatRole::Member)
+        ));
+        assert!(is_allowed(
+            Member,
+            Action::ArchiveChat,
+            Some(ChatRole::Admin)
+        ));
+        assert!(is_allowed(
+            Member,
+            Action::UpdateChat,
+            Some(ChatRole::Member)
+        ));
+        assert!(!is_allowed(Member, Action::UpdateChat, None));
+        assert!(!is_allowed(
+            Guest,
+            Action::UpdateChat,
+            Some(ChatRole::Member)
+        ));
+        assert!(!is_allowed(Guest, Action::CreateChannel, None));
+        assert!(!is_allowed(Guest, Action::JoinChannel, None));
+        assert!(is_allowed(Owner, Action::ManageWorkspace, None));
+        assert!(!is_allowed(
+            Member,
+            Action::PurgeChat,
+            Some(ChatRole::Owner)
+        ));
+        assert!(!is_allowed(Member, Action::ManageWorkspace, None));
+    }
+
+    #[tokio::test]
+    async fn authorize_chat_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let chat = state.get_chat_by_id(2).await?.expect("chat should exist");
+        let owner = state.find_user_by_id(1).await?.expect("user should exist");
+        let member = state.find_user_by_id(3).await?.expect("user should exist");
+        let outsider = state.find_user_by_id(4).await?.expect("user should exist");
+
+        assert_eq!(
+            state
+                .authorize_chat(&owner, &chat, Action::ArchiveChat)
+                .await?,
+            WorkspaceRole::Owner
+        );
+        state
+            .authorize_chat(&member, &chat, Action::UpdateChat)
+            .await?;
+        let ret = state
+            .authorize_chat(&member, &chat, Action::ArchiveChat)
+            .await;
+        assert!(matches!(ret, Err(AppError::Forbidden(_))));
+        // Alice created the private channel
+        let creator = state.find_user_by_id(2).await?.expect("user should exist");
+        state
+            .authorize_chat(&creator, &chat, Action::ArchiveChat)
+            .await?;
+        let ret = state
+            .authorize_chat(&outsider, &chat, Action::UpdateChat)
+            .await;
+        assert!(matches!(ret, Err(AppError::Forbidden(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+use chat_core::{Chat, Message};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgConnection};
+use utoipa::ToSchema;
+
+use crate::{AppError, AppState};
+
+const MAX_PINS: i64 = 50;
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PinnedMessage {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub message: Message,
+    pub pinned_by: i64,
+    pub pinned_at: DateTime<Utc>,
+}
+
+/// A chat with its pinned messages.
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatDetail {
+    #[serde(flatten)]
+    pub chat: Chat,
+    pub pins: Vec<PinnedMessage>,
+}
+
+impl AppState {
+    /// Pin a message of the chat, pinning it twice is a no-op.
+    pub async fn pin_message(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<PinnedMessage>, AppError> {
+        let mut tx = self.pg_pool.begin().await?;
+        // the chat row serializes pins of the chat so the limit holds
+        lock_pins(&mut tx, chat_id).await?;
+        sqlx::query(
+            "SELECT 1 FROM visible_messages WHERE id = $1 AND chat_id = $2 AND deleted_at IS NULL",
+        )
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("message id {message_id}")))?;
+        let count: i64 = sqlx::query_scalar(
+            "SELECT count(*) FROM chat_pins WHERE chat_id = $1 AND message_id <> $2",
+        )
+        .bind(chat_id as i64)
+        .bind(message_id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        if count >= MAX_PINS {
+            return Err(AppError::UpdateChatError(format!(
+                "A chat can have at most {MAX_PINS} pins"
+            )));
+        }
+        sqlx::query(
+            r#"
+            INSERT INTO chat_pins (chat_id, message_id, pinned_by)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        self.list_pins(chat_id).await
+    }
+
+    pub async fn unpin_message(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+    ) -> Result<Vec<PinnedMessage>, AppError> {
+        let mut tx = self.pg_pool.begin().await?;
+        lock_pins(&mut tx, chat_id).await?;
+        let ret = sqlx::query("DELETE FROM chat_pins WHERE chat_id = $1 AND message_id = $2")
+            .bind(chat_id as i64)
+            .bind(message_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "pin of message id {message_id}"
+            )));
+        }
+        tx.commit().await?;
+        self.list_pins(chat_id).await
+    }
+
+    /// Pinned messages of the chat, oldest pin first.
+    pub async fn list_pins(&self, chat_id: u64) -> Result<Vec<PinnedMessage>, AppError> {
+        let pins = sqlx::query_as(
+            r#"
+        SELECT m.id, m.chat_id, m.sender_id, m.content, m.files, m.created_at, m.edited_at,
+          m.deleted_at, m.parent_id, m.reply_count, m.last_reply_at, m.expires_at, m.reference,
+          m.content_html, message_reactions_json(m.id) AS reactions, p.pinned_by, p.pinned_at
+        FROM chat_pins p
+        JOIN visible_messages m ON m.id = p.message_id
+        WHERE p.chat_id = $1
+        ORDER BY p.pinned_at, p.message_id
+        "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(pins)
+    }
+}
+
+/// Lock the chat row for changing its pins, pins of an archived chat can't change.
+async fn lock_pins(conn: &mut PgConnection, chat_id: u64) -> Result<(), AppError> {
+    let archived: bool =
+        sqlx::query_scalar("SELECT archived_at IS NOT NULL FROM chats WHERE id = $1 FOR UPDATE")
+            .bind(chat_id as i64)
+            .fetch_optional(conn)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))?;
+    if archived {
+        return Err(AppError::UpdateChatError("Chat is archived".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn pin_message_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.pin_message(1, 3, 2).await?;
+        let pins = state.pin_message(1, 5, 3).await?;
+        assert_eq!(pins.len(), 2);
+        assert_eq!(pins[0].message.id, 3);
+        assert_eq!(pins[0].pinned_by, 2);
+        // pinning twice keeps the first pin
+        let pins = state.pin_message(1, 3, 4).await?;
+        assert_eq!(pins.len(), 2);
+        assert_eq!(pins[0].pinned_by, 2);
+
+        let ret = state.pin_message(2, 3, 1).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        let pins = state.unpin_message(1, 3).await?;
+        assert_eq!(pins.len(), 1);
+        let ret = state.unpin_message(1, 3).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        // pins of archived chats can't change
+        let mut chat = state.get_chat_by_id(1).await?.expect("chat should exist");
+        state.set_chat_archived(&mut chat, true).await?;
+        let ret = state.unpin_message(1, 5).await;
+        assert!(matches!(ret, Err(AppError::UpdateChatError(_))));
+        state.set_chat_archived(&mut chat, false).await?;
+
+        // deleted messages lose their pins
+        let ivena = state.find_user_by_id(1).await?.expect("user should exist");
+        state.delete_message(1, 5, &ivena).await?;
+        assert!(state.list_pins(1).await?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use chat_core::ReactionCount;
+use sqlx::types::Json;
+
+use crate::{AppError, AppState};
+
+const MAX_EMOJI_LEN: usize = 64;
+
+impl AppState {
+    /// React to a message, reacting twice with the same emoji is a no-op.
+    /// Returns the reactions of the message afterwards.
+    pub async fn add_reaction(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+        emoji: &str,
+    ) -> Result<Vec<ReactionCount>, AppError> {
+        let emoji = verify_emoji(emoji)?;
+        self.verify_reactable(chat_id, message_id).await?;
+        sqlx::query(
+            r#"
+            INSERT INTO message_reactions (message_id, user_id, emoji)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .bind(emoji)
+        .execute(&self.pg_pool)
+        .await?;
+        self.fetch_reactions(message_id).await
+    }
+
+    pub async fn remove_reaction(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+        emoji: &str,
+    ) -> Result<Vec<ReactionCount>, AppError> {
+        let emoji = verify_emoji(emoji)?;
+        self.verify_reactable(chat_id, message_id).await?;
+        sqlx::query(
+            r#"
+            DELETE FROM message_reactions
+            WHERE message_id = $1 AND user_id = $2 AND emoji = $3
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .bind(emoji)
+        .execute(&self.pg_pool)
+        .await?;
+        self.fetch_reactions(message_id).await
+    }
+
+    pub async fn fetch_reactions(&self, message_id: u64) -> Result<Vec<ReactionCount>, AppError> {
+        let Json(reactions) = sqlx::query_scalar("SELECT message_reactions_json($1)")
+            .bind(message_id as i64)
+            .fetch_one(&self.pg_pool)
+            .await?;
+        Ok(reactions)
+    }
+
+    /// Reactions need a message of the chat that is not deleted.
+    async fn verify_reactable(&self, chat_id: u64, message_id: u64) -> Result<(), AppError> {
+        sqlx::query(
+            "SELECT 1 FROM visible_messages WHERE id = $1 AND chat_id = $2 AND deleted_at IS NULL",
+        )
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&self.pg_pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("message id {message_id}")))?;
+        Ok(())
+    }
+}
+
+fn verify_emoji(emoji: &str) -> Result<&str, AppError> {
+    let emoji = emoji.trim();
+    if emoji.is_empty() || emoji.chars().count() > MAX_EMOJI_LEN {
+        return Err(AppError::InvalidReaction(format!(
+            "Emoji must be between 1 and {MAX_EMOJI_LEN} characters"
+        )));
+    }
+    Ok(emoji)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::message::ListMessages;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn reactions_should_be_aggregated() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.add_reaction(1, 1, 2, "👍").await?;
+        state.add_reaction(1, 1, 3, "👍").await?;
+        state.add_reaction(1, 1, 3, "👍").await?;
+        let reactions = state.add_reaction(1, 1, 2, "🎉").await?;
+        assert_eq!(reactions.len(), 2);
+        assert_eq!(reactions[0].emoji, "👍");
+        assert_eq!(reactions[0].count, 2);
+        assert_eq!(reactions[0].user_ids, vec![2, 3]);
+
+        let reactions = state.remove_reaction(1, 1, 2, "🎉").await?;
+        assert_eq!(reactions.len(), 1);
+        let ret = state.add_reaction(2, 1, 2, "👍").await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        let ret = state.add_reaction(1, 1, 2, " ").await;
+        assert!(matches!(ret, Err(AppError::InvalidReaction(_))));
+        let ret = state.remove_reaction(1, 1, 2, &"👍".repeat(65)).await;
+        assert!(matches!(ret, Err(AppError::InvalidReaction(_))));
+
+        let input = ListMessages {
+            last_id: Some(2),
+            limit: 1,
+            ..Default::default()
+        };
+        let messages = state.list_messages(input, 1).await?.messages;
+        assert_eq!(messages[0].reactions, reactions);
+        Ok(())
+    }
+}
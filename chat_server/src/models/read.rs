@@ -0,0 +1,193 @@
+use chat_core::ReadMarker;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{AppError, AppState};
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct MarkRead {
+    /// read up to this message, the latest message of the chat if not set
+    #[serde(default, alias = "messageId")]
+    pub message_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct ListReceipts {
+    /// only the members who have seen this message
+    #[serde(default, alias = "messageId")]
+    pub message_id: Option<u64>,
+}
+
+impl AppState {
+    /// Advance the read marker of the user in the chat, it never moves backwards.
+    pub async fn mark_read(
+        &self,
+        input: MarkRead,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<ReadMarker, AppError> {
+        let marker = sqlx::query_as(
+            r#"
+            WITH target AS (
+              SELECT max(id) AS id
+              FROM messages
+              WHERE chat_id = $1 AND ($3::bigint IS NULL OR id = $3)
+            )
+            UPDATE chat_members m
+            SET last_read_message_id = GREATEST(m.last_read_message_id, t.id)
+            FROM target t
+            WHERE m.chat_id = $1 AND m.user_id = $2 AND t.id IS NOT NULL
+            RETURNING m.chat_id, m.user_id, m.last_read_message_id
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(input.message_id.map(|v| v as i64))
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        match (marker, input.message_id) {
+            (Some(marker), _) => Ok(marker),
+            (None, Some(id)) => Err(AppError::NotFound(format!("message id {id}"))),
+            // nothing to read in an empty chat
+            (None, None) => Ok(ReadMarker {
+                chat_id: chat_id as _,
+                user_id: user_id as _,
+                last_read_message_id: None,
+            }),
+        }
+    }
+}
+
+impl AppState {
+    /// Read markers of the other members of the chat. Chats over `receipts_max_members`
+    /// members don't have read receipts.
+    pub async fn list_receipts(
+        &self,
+        input: ListReceipts,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<ReadMarker>, AppError> {
+        let max = self.config.server.receipts_max_members;
+        let members: i64 =
+            sqlx::query_scalar("SELECT count(*) FROM chat_members WHERE chat_id = $1")
+                .bind(chat_id as i64)
+                .fetch_one(&self.pg_pool)
+                .await?;
+        if members as usize > max {
+            return Err(AppError::Forbidden(format!(
+                "Read receipts are disabled for chats over {max} members"
+            )));
+        }
+        // the sender of the message has seen it anyway
+        let sender_id: Option<i64> = match input.message_id {
+            Some(message_id) => Some(
+                sqlx::query_scalar("SELECT sender_id FROM messages WHERE id = $1 AND chat_id = $2")
+                    .bind(message_id as i64)
+                    .bind(chat_id as i64)
+                    .fetch_optional(&self.pg_pool)
+                    .await?
+                    .ok_or_else(|| AppError::NotFound(format!("message id {message_id}")))?,
+            ),
+            None => None,
+        };
+        let receipts = sqlx::query_as(
+            r#"
+            SELECT m.chat_id, m.user_id, m.last_read_message_id
+            FROM chat_members m
+            WHERE m.chat_id = $1 AND m.user_id <> $2
+              AND ($3::bigint IS NULL OR m.last_read_message_id >= $3)
+              AND m.user_id IS DISTINCT FROM $4
+            ORDER BY m.user_id
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(input.message_id.map(|v| v as i64))
+        .bind(sender_id)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(receipts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::chat::{ChatListItem, ListChats};
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn mark_read_should_update_unread_counts() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let general = |chats: Vec<ChatListItem>| chats.into_iter().find(|c| c.chat.id == 1);
+        let chat = general(state.fetch_chats(2, 1, ListChats::default()).await?).unwrap();
+        // Alice sent 2 of the 10 messages in general
+        assert_eq!(chat.unread_count, 8);
+        assert_eq!(chat.mention_count, 0);
+
+        let input = MarkRead {
+            message_id: Some(5),
+        };
+        let marker = state.mark_read(input, 1, 2).await?;
+        assert_eq!(marker.last_read_message_id, Some(5));
+        // read markers don't move backwards
+        let input = MarkRead {
+            message_id: Some(3),
+        };
+        let marker = state.mark_read(input, 1, 2).await?;
+        assert_eq!(marker.last_read_message_id, Some(5));
+        let chat = general(state.fetch_chats(2, 1, ListChats::default()).await?).unwrap();
+        assert_eq!(chat.unread_count, 4);
+
+        let input = MarkRead {
+            message_id: Some(100),
+        };
+        let ret = state.mark_read(input, 1, 2).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        state.mark_read(MarkRead::default(), 1, 2).await?;
+        let chat = general(state.fetch_chats(2, 1, ListChats::default()).await?).unwrap();
+        assert_eq!(chat.unread_count, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_receipts_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = MarkRead {
+            message_id: Some(3),
+        };
+        state.mark_read(input, 1, 2).await?;
+        state.mark_read(MarkRead::default(), 1, 4).await?;
+
+        let receipts = state.list_receipts(ListReceipts::default(), 1, 1).await?;
+        assert_eq!(receipts.len(), 4);
+        assert_eq!(receipts[0].user_id, 2);
+        assert_eq!(receipts[0].last_read_message_id, Some(3));
+        assert_eq!(receipts[1].last_read_message_id, None);
+
+        // Bob sent message 3, so only Alice and Charlie have seen it
+        let input = ListReceipts {
+            message_id: Some(3),
+        };
+        let receipts = state.list_receipts(input, 1, 1).await?;
+        let seen_by: Vec<_> = receipts.iter().map(|r| r.user_id).collect();
+        assert_eq!(seen_by, vec![2, 4]);
+
+        // message ids of other chats are not found
+        let id: i64 = sqlx::query_scalar(
+            r#"
+            INSERT INTO messages (chat_id, sender_id, content, content_html)
+            VALUES (2, 2, 'hi', '<p>hi</p>')
+            RETURNING id
+            "#,
+        )
+        .fetch_one(&state.pg_pool)
+        .await?;
+        let input = ListReceipts {
+            message_id: Some(id as _),
+        };
+        let ret = state.list_receipts(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        Ok(())
+    }
+}
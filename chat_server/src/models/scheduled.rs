@@ -0,0 +1,473 @@
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    models::message::{normalize_content, CreateMessage},
+    AppError, AppState,
+};
+
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(5);
+const SCHEDULER_BATCH_SIZE: i64 = 100;
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledMessage {
+    pub id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub content: String,
+    pub files: Vec<String>,
+    pub send_at: DateTime<Utc>,
+    pub timezone: String,
+    pub created_at: DateTime<Utc>,
+    /// the delivered message
+    pub message_id: Option<i64>,
+    pub sent_at: Option<DateTime<Utc>>,
+    /// why the delivery failed, e.g. the chat was archived in the meantime
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreateScheduledMessage {
+    pub content: String,
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// local time in `timezone`, e.g. `2025-01-06T09:00:00`
+    #[serde(alias = "sendAt")]
+    pub send_at: NaiveDateTime,
+    /// IANA timezone name, UTC by default
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct UpdateScheduledMessage {
+    pub content: Option<String>,
+    pub files: Option<Vec<String>>,
+    #[serde(default, alias = "sendAt")]
+    pub send_at: Option<NaiveDateTime>,
+    pub timezone: Option<String>,
+}
+
+impl AppState {
+    pub async fn create_scheduled_message(
+        &self,
+        input: CreateScheduledMessage,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<ScheduledMessage, AppError> {
+        let content =
+            normalize_content(&input.content, false).map_err(AppError::CreateMessageError)?;
+        self.verify_files(&input.files)?;
+        self.verify_timezone(&input.timezone).await?;
+        match self.get_chat_by_id(chat_id).await? {
+            None => return Err(AppError::NotFound(format!("chat id {chat_id}"))),
+            Some(chat) if chat.archived_at.is_some() => {
+                return Err(AppError::CreateMessageError("Chat is archived".to_string()))
+            }
+            Some(_) => {}
+        }
+        let message = sqlx::query_as(
+            r#"
+            INSERT INTO scheduled_messages (chat_id, sender_id, content, files, send_at, timezone)
+            SELECT $1, $2, $3, $4, $5::timestamp AT TIME ZONE $6, $6
+            WHERE $5::timestamp AT TIME ZONE $6 > now()
+            RETURNING id, chat_id, sender_id, content, files, send_at, timezone, created_at,
+              message_id, sent_at, error
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(content)
+        .bind(&input.files)
+        .bind(input.send_at)
+        .bind(&input.timezone)
+        .fetch_optional(&self.pg_pool)
+        .await?
+        .ok_or_else(|| {
+            AppError::CreateMessageError("Scheduled time must be in the future".to_string())
+        })?;
+        Ok(message)
+    }
+
+    /// Scheduled messages of the user that are not delivered yet, including failed ones.
+    pub async fn list_scheduled_messages(
+        &self,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Vec<ScheduledMessage>, AppError> {
+        let messages = sqlx::query_as(
+            r#"
+            SELECT s.id, s.chat_id, s.sender_id, s.content, s.files, s.send_at, s.timezone,
+              s.created_at, s.message_id, s.sent_at, s.error
+            FROM scheduled_messages s
+            JOIN chats c ON c.id = s.chat_id
+            WHERE s.sender_id = $1 AND c.ws_id = $2 AND s.sent_at IS NULL
+            ORDER BY s.send_at, s.id
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(ws_id as i64)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(messages)
+    }
+
+    /// Edit a pending scheduled message, editing a failed one schedules it again.
+    pub async fn update_scheduled_message(
+        &self,
+        input: UpdateScheduledMessage,
+        id: u64,
+        user_id: u64,
+    ) -> Result<ScheduledMessage, AppError> {
+        let content = input
+            .content
+            .map(|c| normalize_content(&c, false))
+            .transpose()
+            .map_err(AppError::UpdateMessageError)?;
+        if let Some(files) = &input.files {
+            self.verify_files(files)?;
+        }
+        if let Some(timezone) = &input.timezone {
+            self.verify_timezone(timezone).await?;
+        }
+        let mut tx = self.pg_pool.begin().await?;
+        let archived: bool = sqlx::query_scalar(
+            r#"
+            SELECT c.archived_at IS NOT NULL
+            FROM scheduled_messages s
+            JOIN chats c ON c.id = s.chat_id
+            WHERE s.id = $1 AND s.sender_id = $2 AND s.sent_at IS NULL
+            FOR UPDATE OF s
+            "#,
+        )
+        .bind(id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("scheduled message id {id}")))?;
+        if archived {
+            return Err(AppError::UpdateMessageError("Chat is archived".to_string()));
+        }
+        // the local time is kept when only the timezone changes
+        let message: ScheduledMessage = sqlx::query_as(
+            r#"
+            UPDATE scheduled_messages
+            SET content = COALESCE($2, content), files = COALESCE($3, files),
+              send_at = COALESCE($4, send_at AT TIME ZONE timezone) AT TIME ZONE COALESCE($5, timezone),
+              timezone = COALESCE($5, timezone), error = NULL
+            WHERE id = $1
+              AND COALESCE($4, send_at AT TIME ZONE timezone) AT TIME ZONE COALESCE($5, timezone) > now()
+            RETURNING id, chat_id, sender_id, content, files, send_at, timezone, created_at,
+              message_id, sent_at, error
+            "#,
+        )
+        .bind(id as i64)
+        .bind(content)
+        .bind(input.files)
+        .bind(input.send_at)
+        .bind(input.timezone)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| {
+            AppError::UpdateMessageError("Scheduled time must be in the future".to_string())
+        })?;
+        tx.commit().await?;
+        Ok(message)
+    }
+
+    pub async fn cancel_scheduled_message(&self, id: u64, user_id: u64) -> Result<(), AppError> {
+        let ret = sqlx::query(
+            r#"
+            DELETE FROM scheduled_messages
+            WHERE id = $1 AND sender_id = $2 AND sent_at IS NULL
+            "#,
+        )
+        .bind(id as i64)
+        .bind(user_id as i64)
+        .execute(&self.pg_pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("scheduled message id {id}")));
+        }
+        Ok(())
+    }
+
+    /// Send the scheduled messages that are due, returns how many were handled.
+    /// Rows are locked with `SKIP LOCKED` so several chat_server instances can run this.
+    pub async fn deliver_scheduled_messages(&self) -> Result<usize, AppError> {
+        let mut tx = self.pg_pool.begin().await?;
+        let due: Vec<ScheduledMessage> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, content, files, send_at, timezone, created_at,
+              message_id, sent_at, error
+            FROM scheduled_messages
+            WHERE sent_at IS NULL AND error IS NULL AND send_at <= now()
+            ORDER BY send_at, id
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+            "#,
+        )
+        .bind(SCHEDULER_BATCH_SIZE)
+        .fetch_all(&mut *tx)
+        .await?;
+        for scheduled in &due {
+            let ret = self.deliver_scheduled_message(scheduled).await;
+            let (message_id, error) = match ret {
+                Ok(id) => (Some(id), None),
+                // the message can't be sent as it is, the sender has to change it
+                Err(
+                    e @ (AppError::CreateMessageError(_)
+                    | AppError::NotFound(_)
+                    | AppError::Forbidden(_)
+                    | AppError::ChatFileError(_)),
+                ) => {
+                    warn!(
+                        "Failed to deliver scheduled message {}: {}",
+                        scheduled.id, e
+                    );
+                    (None, Some(e.to_string()))
+                }
+                // retried on the next run
+                Err(e) => {
+                    warn!(
+                        "Failed to deliver scheduled message {}, will retry: {:?}",
+                        scheduled.id, e
+                    );
+                    continue;
+                }
+            };
+            sqlx::query(
+                r#"
+                UPDATE scheduled_messages
+                SET message_id = $2, sent_at = CASE WHEN $3::text IS NULL THEN now() END,
+                  error = $3
+                WHERE id = $1
+                "#,
+            )
+            .bind(scheduled.id)
+            .bind(message_id)
+            .bind(error)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(due.len())
+    }
+
+    /// Goes through `create_message` like a live message would. The message commits on
+    /// its own, the client id makes sure a redelivery returns it instead of sending it
+    /// again.
+    async fn deliver_scheduled_message(
+        &self,
+        scheduled: &ScheduledMessage,
+    ) -> Result<i64, AppError> {
+        let (chat_id, sender_id) = (scheduled.chat_id as u64, scheduled.sender_id as u64);
+        if !self.is_chat_member(chat_id, sender_id).await? {
+            return Err(AppError::CreateMessageError(
+                "Sender is no longer a member of the chat".to_string(),
+            ));
+        }
+        let input = CreateMessage {
+            content: scheduled.content.clone(),
+            files: scheduled.files.clone(),
+            client_id: Some(format!("scheduled:{}", scheduled.id)),
+            ..Default::default()
+        };
+        let message = self.create_message(input, chat_id, sender_id).await?;
+        Ok(message.id)
+    }
+
+    async fn verify_timezone(&self, timezone: &str) -> Result<(), AppError> {
+        sqlx::query("SELECT 1 FROM pg_timezone_names WHERE name = $1")
+            .bind(timezone)
+            .fetch_optional(&self.pg_pool)
+            .await?
+            .ok_or_else(|| AppError::CreateMessageError(format!("Unknown timezone {timezone}")))?;
+        Ok(())
+    }
+}
+
+/// Start the background worker delivering scheduled messages, once per server.
+pub fn setup_scheduler(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SCHEDULER_INTERVAL);
+        loop {
+            interval.tick().await;
+            match state.deliver_scheduled_messages().await {
+                Ok(0) => {}
+                Ok(n) => info!("Delivered {} scheduled messages", n),
+                Err(e) => warn!("Failed to deliver scheduled messages: {:?}", e),
+            }
+        }
+    });
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::markdown::MAX_CONTENT_LENGTH;
+    use anyhow::Result;
+    use chrono::NaiveDate;
+
+    fn create_input(
+        content: &str,
+        send_at: NaiveDateTime,
+        timezone: &str,
+    ) -> CreateScheduledMessage {
+        CreateScheduledMessage {
+            content: content.to_string(),
+            files: vec![],
+            send_at,
+            timezone: timezone.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn scheduled_message_should_convert_timezone() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let send_at = NaiveDate::from_ymd_opt(2099, 1, 5)
+            .and_then(|d| d.and_hms_opt(9, 0, 0))
+            .expect("valid date");
+        let input = create_input("standup", send_at, "Asia/Shanghai");
+        let message = state.create_scheduled_message(input, 1, 1).await?;
+        assert_eq!(
+            message.send_at.naive_utc(),
+            send_at - chrono::Duration::hours(8)
+        );
+
+        // changing the timezone keeps the local time
+        let input = UpdateScheduledMessage {
+            timezone: Some("UTC".to_string()),
+            ..Default::default()
+        };
+        let message = state
+            .update_scheduled_message(input, message.id as _, 1)
+            .await?;
+        assert_eq!(message.send_at.naive_utc(), send_at);
+        assert_eq!(state.list_scheduled_messages(1, 1).await?.len(), 1);
+        // only the sender can change it
+        let ret = state
+            .update_scheduled_message(UpdateScheduledMessage::default(), message.id as _, 2)
+            .await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        let input = create_input("standup", send_at, "Mars/Olympus");
+        let ret = state.create_scheduled_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+        let input = create_input("standup", Utc::now().naive_utc(), "UTC");
+        let ret = state.create_scheduled_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+        let input = UpdateScheduledMessage {
+            send_at: Some(Utc::now().naive_utc()),
+            ..Default::default()
+        };
+        let ret = state
+            .update_scheduled_message(input, message.id as _, 1)
+            .await;
+        assert!(matches!(ret, Err(AppError::UpdateMessageError(_))));
+
+        state.cancel_scheduled_message(message.id as _, 1).await?;
+        assert!(state.list_scheduled_messages(1, 1).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scheduled_message_content_should_be_validated() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let send_at = Utc::now().naive_utc() + chrono::Duration::hours(1);
+        let input = create_input("hello  \r\n\n\n\nworld\n", send_at, "UTC");
+        let message = state.create_scheduled_message(input, 1, 1).await?;
+        assert_eq!(message.content, "hello\n\nworld");
+
+        let input = create_input(" \n ", send_at, "UTC");
+        let ret = state.create_scheduled_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+        let input = create_input(&"a".repeat(MAX_CONTENT_LENGTH + 1), send_at, "UTC");
+        let ret = state.create_scheduled_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+        let input = UpdateScheduledMessage {
+            content: Some("a".repeat(MAX_CONTENT_LENGTH + 1)),
+            ..Default::default()
+        };
+        let ret = state
+            .update_scheduled_message(input, message.id as _, 1)
+            .await;
+        assert!(matches!(ret, Err(AppError::UpdateMessageError(_))));
+
+        let mut chat = state.get_chat_by_id(1).await?.expect("chat should exist");
+        state.set_chat_archived(&mut chat, true).await?;
+        let input = create_input("hello", send_at, "UTC");
+        let ret = state.create_scheduled_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateMessageError(_))));
+        let input = UpdateScheduledMessage {
+            content: Some("hello".to_string()),
+            ..Default::default()
+        };
+        let ret = state
+            .update_scheduled_message(input, message.id as _, 1)
+            .await;
+        assert!(matches!(ret, Err(AppError::UpdateMessageError(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deliver_scheduled_messages_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let send_at = Utc::now().naive_utc() + chrono::Duration::seconds(60);
+        let input = create_input("reminder", send_at, "UTC");
+        let pending = state.create_scheduled_message(input, 1, 2).await?;
+        let input = create_input("too late", send_at, "UTC");
+        let failing = state.create_scheduled_message(input, 2, 2).await?;
+        sqlx::query("UPDATE scheduled_messages SET send_at = now() - interval '1 second'")
+            .execute(&state.pg_pool)
+            .await?;
+        let mut chat = state.get_chat_by_id(2).await?.expect("chat should exist");
+        state.set_chat_archived(&mut chat, true).await?;
+
+        assert_eq!(state.deliver_scheduled_messages().await?, 2);
+        assert_eq!(state.deliver_scheduled_messages().await?, 0);
+        let scheduled = state.list_scheduled_messages(2, 1).await?;
+        assert_eq!(scheduled.len(), 1);
+        assert_eq!(scheduled[0].id, failing.id);
+        assert!(scheduled[0].error.is_some());
+
+        let message_id: Option<i64> =
+            sqlx::query_scalar("SELECT message_id FROM scheduled_messages WHERE id = $1")
+                .bind(pending.id)
+                .fetch_one(&state.pg_pool)
+                .await?;
+        let message = state
+            .get_message_by_id(1, message_id.expect("message should be sent") as _)
+            .await?;
+        assert_eq!(message.content, "reminder");
+        assert_eq!(message.sender_id, 2);
+
+        // a crash before the delivery was recorded doesn't send the message twice
+        let delivered: ScheduledMessage = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, content, files, send_at, timezone, created_at,
+              message_id, sent_at, error
+            FROM scheduled_messages
+            WHERE id = $1
+            "#,
+        )
+        .bind(pending.id)
+        .fetch_one(&state.pg_pool)
+        .await?;
+        assert_eq!(
+            state.deliver_scheduled_message(&delivered).await?,
+            message.id
+        );
+        Ok(())
+    }
+}
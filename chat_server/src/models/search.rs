@@ -0,0 +1,193 @@
+use chat_core::Message;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{AppError, AppState};
+
+#[derive(Debug, Clone, Default, Serialize, IntoParams, ToSchema, Deserialize)]
+pub struct SearchMessages {
+    /// search terms with optional filters: `from:<user id, email or name>`,
+    /// `in:<chat id or name>`, `before:<yyyy-mm-dd>`, `after:<yyyy-mm-dd>` and `has:file`
+    pub q: String,
+    #[serde(default)]
+    pub last_id: Option<u64>,
+    #[serde(default)]
+    pub limit: u64,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct SearchResult {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub message: Message,
+    /// html escaped content with the matches wrapped in `<mark>`
+    pub snippet: String,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct SearchQuery {
+    text: String,
+    from: Option<String>,
+    chat: Option<String>,
+    before: Option<NaiveDate>,
+    after: Option<NaiveDate>,
+    has_file: bool,
+}
+
+impl AppState {
+    /// Search the messages of the chats the user is a member of, newest first.
+    pub async fn search_messages(
+        &self,
+        input: SearchMessages,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Vec<SearchResult>, AppError> {
+        let query = SearchQuery::parse(&input.q)?;
+        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+        let limit = match input.limit {
+            1..=100 => input.limit as _,
+            _ => 20i64,
+        };
+        let results = sqlx::query_as(
+            r#"
+        SELECT m.id, m.chat_id, m.sender_id, m.content, m.files, m.created_at, m.edited_at,
+          m.deleted_at, m.parent_id, m.reply_count, m.last_reply_at, m.expires_at, m.reference,
+          m.content_html, message_reactions_json(m.id) AS reactions,
+          CASE WHEN $4 = '' THEN html_escape(left(m.content, 200))
+          ELSE ts_headline('simple', html_escape(m.content), websearch_to_tsquery('simple', $4),
+            'StartSel=<mark>, StopSel=</mark>')
+          END AS snippet
+        FROM visible_messages m
+        JOIN chats c ON c.id = m.chat_id
+        JOIN chat_members cm ON cm.chat_id = m.chat_id AND cm.user_id = $1
+        JOIN users u ON u.id = m.sender_id
+        WHERE c.ws_id = $2 AND m.id < $3 AND m.deleted_at IS NULL
+          AND ($4 = '' OR m.content_tsv @@ websearch_to_tsquery('simple', $4))
+          AND ($5::text IS NULL OR m.sender_id::text = $5 OR lower(u.email) = lower($5)
+            OR lower(u.username) = lower($5))
+          AND ($6::text IS NULL OR c.id::text = $6 OR lower(c.name) = lower($6))
+          AND ($7::date IS NULL OR m.created_at < $7)
+          AND ($8::date IS NULL OR m.created_at >= $8 + 1)
+          AND (NOT $9 OR cardinality(m.files) > 0)
+        ORDER BY m.id DESC
+        LIMIT $10
+        "#,
+        )
+        .bind(user_id as i64)
+        .bind(ws_id as i64)
+        .bind(last_id as i64)
+        .bind(&query.text)
+        .bind(&query.from)
+        .bind(&query.chat)
+        .bind(query.before)
+        .bind(query.after)
+        .bind(query.has_file)
+        .bind(limit)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(results)
+    }
+}
+
+impl SearchQuery {
+    fn parse(q: &str) -> Result<Self, AppError> {
+        let mut query = Self::default();
+        let mut terms = vec![];
+        for token in q.split_whitespace() {
+            match token.split_once(':') {
+                Some(("from", v)) if !v.is_empty() => query.from = Some(v.to_string()),
+                Some(("in", v)) if !v.is_empty() => query.chat = Some(v.to_string()),
+                Some(("before", v)) => query.before = Some(parse_date(v)?),
+                Some(("after", v)) => query.after = Some(parse_date(v)?),
+                Some(("has", "file")) => query.has_file = true,
+                _ => terms.push(token),
+            }
+        }
+        query.text = terms.join(" ");
+        if query == Self::default() {
+            return Err(AppError::InvalidSearch("query is empty".to_string()));
+        }
+        Ok(query)
+    }
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate, AppError> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| AppError::InvalidSearch(format!("invalid date {s}, expected yyyy-mm-dd")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::message::CreateMessage;
+    use anyhow::Result;
+
+    #[test]
+    fn search_query_parse_should_work() -> Result<()> {
+        let query = SearchQuery::parse("deploy from:alice in:general after:2024-12-01 has:file")?;
+        assert_eq!(
+            query,
+            SearchQuery {
+                text: "deploy".to_string(),
+                from: Some("alice".to_string()),
+                chat: Some("general".to_string()),
+                after: NaiveDate::from_ymd_opt(2024, 12, 1),
+                has_file: true,
+                ..Default::default()
+            }
+        );
+        assert!(SearchQuery::parse("before:yesterday").is_err());
+        assert!(SearchQuery::parse("  ").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_messages_should_only_return_member_chats() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "release <b>notes</b> are out".to_string(),
+            ..Default::default()
+        };
+        let private = state.create_message(input, 2, 2).await?;
+        let input = CreateMessage {
+            content: "where are the release notes?".to_string(),
+            ..Default::default()
+        };
+        let general = state.create_message(input, 1, 3).await?;
+
+        let input = SearchMessages {
+            q: "release notes".to_string(),
+            ..Default::default()
+        };
+        let results = state.search_messages(input.clone(), 1, 1).await?;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].message.id, general.id);
+        assert_eq!(results[1].message.id, private.id);
+        assert_eq!(
+            results[1].snippet,
+            "<mark>release</mark> &lt;b&gt;<mark>notes</mark>&lt;/b&gt; are out"
+        );
+
+        // Charlie is not in the private channel
+        let results = state.search_messages(input, 4, 1).await?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].message.id, general.id);
+
+        let input = SearchMessages {
+            q: "release in:private from:2".to_string(),
+            ..Default::default()
+        };
+        let results = state.search_messages(input, 1, 1).await?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].message.id, private.id);
+
+        let input = SearchMessages {
+            q: "release has:file".to_string(),
+            ..Default::default()
+        };
+        assert!(state.search_messages(input, 1, 1).await?.is_empty());
+        Ok(())
+    }
+}
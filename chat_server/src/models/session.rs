@@ -0,0 +1,212 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::prelude::FromRow;
+use utoipa::ToSchema;
+
+use crate::{AppError, AppState};
+
+const REFRESH_TOKEN_DURATION: i64 = 60 * 60 * 24 * 30;
+const REFRESH_TOKEN_BYTES: usize = 32;
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub id: i64,
+    pub user_id: i64,
+    pub ws_id: Option<i64>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub refreshed_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshToken {
+    pub refresh_token: String,
+}
+
+impl AppState {
+    /// Create a new session for the user, returns the session and its refresh token.
+    pub async fn create_session(
+        &self,
+        user_id: i64,
+        ws_id: i64,
+        user_agent: Option<&str>,
+    ) -> Result<(Session, String), AppError> {
+        let token = generate_refresh_token();
+        let session = sqlx::query_as(
+            r#"
+            INSERT INTO sessions (user_id, ws_id, refresh_token_hash, user_agent, expires_at)
+            VALUES ($1, $2, $3, $4, now() + make_interval(secs => $5))
+            RETURNING id, user_id, ws_id, user_agent, created_at, refreshed_at, expires_at, revoked_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(ws_id)
+        .bind(hash_refresh_token(&token))
+        .bind(user_agent)
+        .bind(REFRESH_TOKEN_DURATION as f64)
+        .fetch_one(&self.pg_pool)
+        .await?;
+        Ok((session, token))
+    }
+
+    /// Exchange a refresh token for a new one. The old token stops working immediately.
+    pub async fn refresh_session(&self, token: &str) -> Result<(Session, String), AppError> {
+        let new_token = generate_refresh_token();
+        let session = sqlx::query_as(
+            r#"
+            UPDATE sessions
+            SET refresh_token_hash = $2, refreshed_at = now(),
+                expires_at = now() + make_interval(secs => $3)
+            WHERE refresh_token_hash = $1 AND revoked_at IS NULL AND expires_at > now()
+            RETURNING id, user_id, ws_id, user_agent, created_at, refreshed_at, expires_at, revoked_at
+            "#,
+        )
+        .bind(hash_refresh_token(token))
+        .bind(hash_refresh_token(&new_token))
+        .bind(REFRESH_TOKEN_DURATION as f64)
+        .fetch_optional(&self.pg_pool)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("Invalid or expired refresh token".to_string()))?;
+        Ok((session, new_token))
+    }
+
+    /// Scope the session to another workspace of the user, the refresh token is rotated.
+    pub async fn switch_session_workspace(
+        &self,
+        id: i64,
+        user_id: i64,
+        ws_id: i64,
+    ) -> Result<(Session, String), AppError> {
+        let token = generate_refresh_token();
+        let session = sqlx::query_as(
+            r#"
+            UPDATE sessions
+            SET ws_id = $3, refresh_token_hash = $4, refreshed_at = now()
+            WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL AND expires_at > now()
+            RETURNING id, user_id, ws_id, user_agent, created_at, refreshed_at, expires_at, revoked_at
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(ws_id)
+        .bind(hash_refresh_token(&token))
+        .fetch_optional(&self.pg_pool)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("Session has been revoked".to_string()))?;
+        Ok((session, token))
+    }
+
+    pub async fn revoke_session(&self, id: i64, user_id: i64) -> Result<(), AppError> {
+        let ret = sqlx::query(
+            r#"
+            UPDATE sessions
+            SET revoked_at = now()
+            WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(&self.pg_pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("session id {id}")));
+        }
+        Ok(())
+    }
+
+    pub async fn fetch_sessions(&self, user_id: i64) -> Result<Vec<Session>, AppError> {
+        let sessions = sqlx::query_as(
+            r#"
+            SELECT id, user_id, ws_id, user_agent, created_at, refreshed_at, expires_at, revoked_at
+            FROM sessions
+            WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > now()
+            ORDER BY refreshed_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(sessions)
+    }
+
+    /// Whether the session is still valid and the user is still a member of the workspace.
+    pub async fn is_session_active(
+        &self,
+        id: i64,
+        user_id: i64,
+        ws_id: i64,
+    ) -> Result<bool, AppError> {
+        let ret = sqlx::query(
+            r#"
+            SELECT 1
+            FROM sessions s
+            JOIN workspace_members m ON m.user_id = s.user_id AND m.ws_id = $3
+            WHERE s.id = $1 AND s.user_id = $2 AND s.revoked_at IS NULL AND s.expires_at > now()
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(ws_id)
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        Ok(ret.is_some())
+    }
+}
+
+fn generate_refresh_token() -> String {
+    let mut buf = [0u8; REFRESH_TOKEN_BYTES];
+    OsRng.fill_bytes(&mut buf);
+    hex::encode(buf)
+}
+
+fn hash_refresh_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn refresh_session_should_rotate_token() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let (session, token) = state.create_session(1, 1, Some("test")).await?;
+        assert!(state.is_session_active(session.id, 1, 1).await?);
+        // Ivena is not a member of bar
+        assert!(!state.is_session_active(session.id, 1, 3).await?);
+
+        let (refreshed, new_token) = state.refresh_session(&token).await?;
+        assert_eq!(refreshed.id, session.id);
+        assert_ne!(token, new_token);
+
+        // the old refresh token can't be used again
+        let ret = state.refresh_session(&token).await;
+        assert!(matches!(ret, Err(AppError::Unauthorized(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revoke_session_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let (session, token) = state.create_session(1, 1, None).await?;
+        assert_eq!(state.fetch_sessions(1).await?.len(), 1);
+
+        // other users can't revoke the session
+        let ret = state.revoke_session(session.id, 2).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        state.revoke_session(session.id, 1).await?;
+        assert!(!state.is_session_active(session.id, 1, 1).await?);
+        assert!(state.fetch_sessions(1).await?.is_empty());
+        let ret = state.refresh_session(&token).await;
+        assert!(matches!(ret, Err(AppError::Unauthorized(_))));
+        Ok(())
+    }
+}
@@ -5,7 +5,7 @@ use argon2::{
     Argon2, PasswordHash, PasswordVerifier,
 };
 
-use chat_core::{ChatUser, User};
+use chat_core::{ChatUser, User, WorkspaceRole};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
@@ -17,6 +17,9 @@ pub struct CreateUser {
     pub email: String,
     pub workspace: String,
     pub password: String,
+    /// required to join a workspace that already exists
+    #[serde(default, alias = "inviteCode")]
+    pub invite_code: Option<String>,
 }
 #[derive(Debug, Serialize, ToSchema, Deserialize)]
 pub struct SigninUser {
@@ -37,14 +40,26 @@ impl AppState {
     }
 
     pub async fn create_user(&self, input: &CreateUser) -> Result<User, AppError> {
+        if self.find_user_by_email(&input.email).await?.is_some() {
+            return Err(AppError::EmailAlreadyExists(input.email.clone()));
+        }
         let password_hash = hash_password(&input.password)?;
 
         // start a transaction
         let mut tx = self.pg_pool.begin().await?;
 
-        // search for the workspace
+        // search for the workspace, joining an existing one requires an invite
         let ws = match self.find_workspace_by_name(&input.workspace).await? {
-            Some(ws) => ws,
+            Some(ws) => {
+                let Some(code) = &input.invite_code else {
+                    return Err(AppError::InvalidInvite(format!(
+                        "Workspace {} requires an invite",
+                        ws.name
+                    )));
+                };
+                self.redeem_invite(&mut tx, ws.id, code).await?;
+                ws
+            }
             None => self.create_workspace(&input.workspace, 0).await?,
         };
 
@@ -86,6 +101,15 @@ impl AppState {
             }
         };
 
+        // whoever creates the workspace owns it
+        let role = if ws.owner_id == 0 {
+            WorkspaceRole::Owner
+        } else {
+            WorkspaceRole::Member
+        };
+        self.add_workspace_member(&mut tx, ws.id, user.id, role)
+            .await?;
+
         // if the workspace owner is not set, set it to the current user
         if ws.owner_id == 0 {
             sqlx::query("UPDATE workspaces SET owner_id = $1 WHERE id = $2")
@@ -128,14 +152,21 @@ impl AppState {
 }
 
 impl AppState {
-    pub async fn fetch_chat_user_by_ids(&self, ids: &[i64]) -> Result<Vec<ChatUser>, AppError> {
+    /// Users with the given ids, limited to members of the workspace.
+    pub async fn fetch_chat_user_by_ids(
+        &self,
+        ws_id: i64,
+        ids: &[i64],
+    ) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             r#"
-        SELECT id, username, email
-        FROM users
-        WHERE id = ANY($1)
+        SELECT u.id, u.username, u.email
+        FROM users u
+        JOIN workspace_members m ON m.user_id = u.id
+        WHERE m.ws_id = $1 AND u.id = ANY($2)
         "#,
         )
+        .bind(ws_id)
         .bind(ids)
         .fetch_all(&self.pg_pool)
         .await?;
@@ -145,9 +176,10 @@ impl AppState {
     pub async fn fetch_chat_users(&self, ws_id: i64) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             r#"
-        SELECT id, username, email
-        FROM users
-        WHERE ws_id = $1
+        SELECT u.id, u.username, u.email
+        FROM users u
+        JOIN workspace_members m ON m.user_id = u.id
+        WHERE m.ws_id = $1
         "#,
         )
         .bind(ws_id)
@@ -214,8 +246,14 @@ impl CreateUser {
             workspace: ws.to_string(),
             email: email.to_string(),
             password: password.to_string(),
+            invite_code: None,
         }
     }
+
+    pub fn with_invite(mut self, code: &str) -> Self {
+        self.invite_code = Some(code.to_string());
+        self
+    }
 }
 
 #[cfg(test)]
@@ -269,7 +307,7 @@ mod tests {
     async fn create_and_verify_user_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
 
-        let input = CreateUser::new("none", "test", "test@test.test", "hunter42");
+        let input = CreateUser::new("test", "test", "test@test.test", "hunter42");
         let user = state.create_user(&input).await?;
         assert_eq!(user.email, input.email);
         assert_eq!(user.username, input.username);
@@ -287,4 +325,23 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn create_user_in_existing_workspace_should_require_invite() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let input = CreateUser::new("acme", "test", "test@acme.org", "hunter42");
+        let ret = state.create_user(&input).await;
+        assert!(matches!(ret, Err(AppError::InvalidInvite(_))));
+
+        let input = input.with_invite("bad-code");
+        let ret = state.create_user(&input).await;
+        assert!(matches!(ret, Err(AppError::InvalidInvite(_))));
+
+        let invite = state.create_invite(Default::default(), 1, 1).await?;
+        let input = input.with_invite(&invite.code);
+        let user = state.create_user(&input).await?;
+        assert_eq!(user.ws_id, 1);
+        Ok(())
+    }
 }
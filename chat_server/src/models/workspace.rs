@@ -1,5 +1,60 @@
-use crate::{AppError, AppState};
-use chat_core::{ChatUser, Workspace};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgConnection};
+use utoipa::ToSchema;
+
+use crate::{models::permission::Action, AppError, AppState};
+use chat_core::{ChatUser, Workspace, WorkspaceRole};
+
+const INVITE_CODE_BYTES: usize = 8;
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceInvite {
+    pub id: i64,
+    pub ws_id: i64,
+    pub code: String,
+    pub created_by: i64,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct CreateInvite {
+    /// how many users can sign up with the invite, unlimited if not set
+    #[serde(default, alias = "maxUses")]
+    pub max_uses: Option<i32>,
+    /// seconds until the invite expires, never if not set
+    #[serde(default, alias = "expiresIn")]
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct AcceptInvite {
+    pub username: String,
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceMember {
+    pub id: i64,
+    pub username: String,
+    pub email: String,
+    pub role: WorkspaceRole,
+    pub joined_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct UpdateMemberRole {
+    pub role: WorkspaceRole,
+}
+
 impl AppState {
     pub async fn create_workspace(&self, name: &str, user_id: u64) -> Result<Workspace, AppError> {
         let ws = sqlx::query_as(
@@ -19,9 +74,10 @@ impl AppState {
     pub async fn fetch_all_chat_users(&self, id: u64) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             r#"
-        SELECT id, username, email
-        FROM users
-        WHERE ws_id = $1 order by id
+        SELECT u.id, u.username, u.email
+        FROM users u
+        JOIN workspace_members m ON m.user_id = u.id
+        WHERE m.ws_id = $1 order by u.id
         "#,
         )
         .bind(id as i64)
@@ -44,7 +100,280 @@ impl AppState {
         .await?;
         Ok(ws)
     }
-    #[allow(dead_code)]
+    /// All workspaces the user is a member of.
+    pub async fn fetch_workspaces(&self, user_id: u64) -> Result<Vec<Workspace>, AppError> {
+        let workspaces = sqlx::query_as(
+            r#"
+            SELECT w.id, w.name, w.owner_id, w.created_at
+            FROM workspaces w
+            JOIN workspace_members m ON m.ws_id = w.id
+            WHERE m.user_id = $1
+            ORDER BY m.joined_at, w.id
+            "#,
+        )
+        .bind(user_id as i64)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(workspaces)
+    }
+
+    /// Find the workspace if the user is a member of it.
+    pub async fn find_member_workspace(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Option<Workspace>, AppError> {
+        let ws = sqlx::query_as(
+            r#"
+            SELECT w.id, w.name, w.owner_id, w.created_at
+            FROM workspaces w
+            JOIN workspace_members m ON m.ws_id = w.id
+            WHERE w.id = $1 AND m.user_id = $2
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        Ok(ws)
+    }
+
+    pub(crate) async fn add_workspace_member(
+        &self,
+        conn: &mut PgConnection,
+        ws_id: i64,
+        user_id: i64,
+        role: WorkspaceRole,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO workspace_members (ws_id, user_id, role)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(ws_id)
+        .bind(user_id)
+        .bind(role)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn fetch_workspace_members(
+        &self,
+        ws_id: u64,
+    ) -> Result<Vec<WorkspaceMember>, AppError> {
+        let members = sqlx::query_as(
+            r#"
+            SELECT u.id, u.username, u.email, m.role, m.joined_at
+            FROM workspace_members m
+            JOIN users u ON u.id = m.user_id
+            WHERE m.ws_id = $1
+            ORDER BY u.id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(members)
+    }
+
+    /// Change the role of a member. Admins manage members and guests, granting or
+    /// revoking admin is up to the owner, and ownership can't be changed here.
+    pub async fn update_member_role(
+        &self,
+        ws_id: u64,
+        actor_id: u64,
+        user_id: u64,
+        role: WorkspaceRole,
+    ) -> Result<WorkspaceMember, AppError> {
+        let actor_role = self
+            .authorize(ws_id, actor_id, Action::ManageWorkspace)
+            .await?;
+        let current = self
+            .workspace_role(ws_id as _, user_id as _)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("member id {user_id}")))?;
+        if role == WorkspaceRole::Owner || current == WorkspaceRole::Owner {
+            return Err(AppError::Forbidden(
+                "The workspace owner can't be changed".to_string(),
+            ));
+        }
+        if (role == WorkspaceRole::Admin || current == WorkspaceRole::Admin)
+            && actor_role != WorkspaceRole::Owner
+        {
+            return Err(AppError::Forbidden(
+                "Only the workspace owner can manage admins".to_string(),
+            ));
+        }
+        let member = sqlx::query_as(
+            r#"
+            WITH m AS (
+              UPDATE workspace_members
+              SET role = $3
+              WHERE ws_id = $1 AND user_id = $2
+              RETURNING user_id, role, joined_at
+            )
+            SELECT u.id, u.username, u.email, m.role, m.joined_at
+            FROM m
+            JOIN users u ON u.id = m.user_id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(role)
+        .fetch_one(&self.pg_pool)
+        .await?;
+        Ok(member)
+    }
+
+    /// Join the workspace of the invite as an existing user.
+    pub async fn join_workspace(&self, code: &str, user_id: u64) -> Result<Workspace, AppError> {
+        let ws = self.find_workspace_by_invite(code).await?;
+        if self
+            .find_member_workspace(ws.id as _, user_id)
+            .await?
+            .is_some()
+        {
+            return Ok(ws);
+        }
+        let mut tx = self.pg_pool.begin().await?;
+        self.redeem_invite(&mut tx, ws.id, code).await?;
+        self.add_workspace_member(&mut tx, ws.id, user_id as _, WorkspaceRole::Member)
+            .await?;
+        tx.commit().await?;
+        Ok(ws)
+    }
+
+    /// Remember the workspace as the one to sign in to next time.
+    pub async fn set_default_workspace(&self, user_id: u64, ws_id: u64) -> Result<(), AppError> {
+        sqlx::query("UPDATE users SET ws_id = $1 WHERE id = $2")
+            .bind(ws_id as i64)
+            .bind(user_id as i64)
+            .execute(&self.pg_pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn create_invite(
+        &self,
+        input: CreateInvite,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<WorkspaceInvite, AppError> {
+        self.authorize(ws_id, user_id, Action::ManageWorkspace)
+            .await?;
+        if input.max_uses.is_some_and(|v| v < 1) {
+            return Err(AppError::InvalidInvite(
+                "max_uses must be at least 1".to_string(),
+            ));
+        }
+        let invite = sqlx::query_as(
+            r#"
+            INSERT INTO workspace_invites (ws_id, code, created_by, max_uses, expires_at)
+            VALUES ($1, $2, $3, $4, now() + make_interval(secs => $5))
+            RETURNING id, ws_id, code, created_by, max_uses, uses, expires_at, revoked_at, created_at
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(generate_invite_code())
+        .bind(user_id as i64)
+        .bind(input.max_uses)
+        .bind(input.expires_in.map(|v| v as f64))
+        .fetch_one(&self.pg_pool)
+        .await?;
+        Ok(invite)
+    }
+
+    pub async fn fetch_invites(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<WorkspaceInvite>, AppError> {
+        self.authorize(ws_id, user_id, Action::ManageWorkspace)
+            .await?;
+        let invites = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, code, created_by, max_uses, uses, expires_at, revoked_at, created_at
+            FROM workspace_invites
+            WHERE ws_id = $1
+            ORDER BY id DESC
+            "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(invites)
+    }
+
+    pub async fn revoke_invite(&self, id: u64, ws_id: u64, user_id: u64) -> Result<(), AppError> {
+        self.authorize(ws_id, user_id, Action::ManageWorkspace)
+            .await?;
+        let ret = sqlx::query(
+            r#"
+            UPDATE workspace_invites
+            SET revoked_at = now()
+            WHERE id = $1 AND ws_id = $2 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(id as i64)
+        .bind(ws_id as i64)
+        .execute(&self.pg_pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("invite id {id}")));
+        }
+        Ok(())
+    }
+
+    /// Find the workspace a still usable invite code belongs to.
+    pub async fn find_workspace_by_invite(&self, code: &str) -> Result<Workspace, AppError> {
+        let ws = sqlx::query_as(
+            r#"
+            SELECT w.id, w.name, w.owner_id, w.created_at
+            FROM workspace_invites i
+            JOIN workspaces w ON w.id = i.ws_id
+            WHERE i.code = $1 AND i.revoked_at IS NULL
+              AND (i.expires_at IS NULL OR i.expires_at > now())
+              AND (i.max_uses IS NULL OR i.uses < i.max_uses)
+            "#,
+        )
+        .bind(code)
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        ws.ok_or_else(|| AppError::InvalidInvite("Invite is invalid or has expired".to_string()))
+    }
+
+    /// Use up one invite for the workspace. Runs in the caller's transaction so a
+    /// failed signup doesn't consume the invite.
+    pub(crate) async fn redeem_invite(
+        &self,
+        conn: &mut PgConnection,
+        ws_id: i64,
+        code: &str,
+    ) -> Result<(), AppError> {
+        let ret = sqlx::query(
+            r#"
+            UPDATE workspace_invites
+            SET uses = uses + 1
+            WHERE code = $1 AND ws_id = $2 AND revoked_at IS NULL
+              AND (expires_at IS NULL OR expires_at > now())
+              AND (max_uses IS NULL OR uses < max_uses)
+            "#,
+        )
+        .bind(code)
+        .bind(ws_id)
+        .execute(conn)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::InvalidInvite(
+                "Invite is invalid or has expired".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
     pub async fn find_workspace_by_id(&self, id: u64) -> Result<Option<Workspace>, AppError> {
         let ws = sqlx::query_as(
             r#"
@@ -60,6 +389,12 @@ impl AppState {
     }
 }
 
+fn generate_invite_code() -> String {
+    let mut buf = [0u8; INVITE_CODE_BYTES];
+    OsRng.fill_bytes(&mut buf);
+    hex::encode(buf)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -81,4 +416,104 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn invite_should_respect_max_uses_and_revoke() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateInvite {
+            max_uses: Some(1),
+            expires_in: Some(3600),
+        };
+        // only admins of acme can create invites
+        let ret = state.create_invite(input.clone(), 1, 2).await;
+        assert!(matches!(ret, Err(AppError::Forbidden(_))));
+
+        let invite = state.create_invite(input, 1, 1).await?;
+        assert_eq!(invite.ws_id, 1);
+        assert!(invite.expires_at.is_some());
+        let ws = state.find_workspace_by_invite(&invite.code).await?;
+        assert_eq!(ws.name, "acme");
+
+        let mut conn = state.pg_pool.acquire().await?;
+        // invite codes are bound to their workspace
+        let ret = state.redeem_invite(&mut conn, 2, &invite.code).await;
+        assert!(matches!(ret, Err(AppError::InvalidInvite(_))));
+        state.redeem_invite(&mut conn, 1, &invite.code).await?;
+        let ret = state.redeem_invite(&mut conn, 1, &invite.code).await;
+        assert!(matches!(ret, Err(AppError::InvalidInvite(_))));
+
+        let invite = state.create_invite(CreateInvite::default(), 1, 1).await?;
+        state.revoke_invite(invite.id as _, 1, 1).await?;
+        let ret = state.find_workspace_by_invite(&invite.code).await;
+        assert!(matches!(ret, Err(AppError::InvalidInvite(_))));
+        assert_eq!(state.fetch_invites(1, 1).await?.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_member_role_should_respect_roles() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let members = state.fetch_workspace_members(1).await?;
+        assert_eq!(members.len(), 5);
+        assert_eq!(members[0].role, WorkspaceRole::Owner);
+        assert_eq!(members[1].role, WorkspaceRole::Member);
+
+        // members can't change roles
+        let ret = state
+            .update_member_role(1, 2, 3, WorkspaceRole::Guest)
+            .await;
+        assert!(matches!(ret, Err(AppError::Forbidden(_))));
+
+        let member = state
+            .update_member_role(1, 1, 2, WorkspaceRole::Admin)
+            .await?;
+        assert_eq!(member.role, WorkspaceRole::Admin);
+        let member = state
+            .update_member_role(1, 2, 3, WorkspaceRole::Guest)
+            .await?;
+        assert_eq!(member.role, WorkspaceRole::Guest);
+
+        // admins can't touch other admins or the owner
+        let ret = state
+            .update_member_role(1, 2, 3, WorkspaceRole::Admin)
+            .await;
+        assert!(matches!(ret, Err(AppError::Forbidden(_))));
+        let ret = state
+            .update_member_role(1, 2, 1, WorkspaceRole::Member)
+            .await;
+        assert!(matches!(ret, Err(AppError::Forbidden(_))));
+        let ret = state
+            .update_member_role(1, 1, 6, WorkspaceRole::Member)
+            .await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn join_workspace_should_add_membership() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let workspaces = state.fetch_workspaces(2).await?;
+        assert_eq!(workspaces.len(), 1);
+        assert!(state.find_member_workspace(2, 2).await?.is_none());
+
+        // Ivena owns acme and is a member of foo as well
+        let workspaces = state.fetch_workspaces(1).await?;
+        assert_eq!(
+            workspaces.iter().map(|w| w.id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        sqlx::query("UPDATE workspace_members SET role = 'admin' WHERE ws_id = 2 AND user_id = 1")
+            .execute(&state.pg_pool)
+            .await?;
+        let invite = state.create_invite(CreateInvite::default(), 2, 1).await?;
+
+        let ws = state.join_workspace(&invite.code, 2).await?;
+        assert_eq!(ws.id, 2);
+        assert!(state.find_member_workspace(2, 2).await?.is_some());
+        assert_eq!(state.fetch_all_chat_users(2).await?.len(), 2);
+        // joining again doesn't use up the invite
+        state.join_workspace(&invite.code, 2).await?;
+        assert_eq!(state.fetch_invites(2, 1).await?[0].uses, 1);
+        Ok(())
+    }
 }
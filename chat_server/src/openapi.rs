@@ -1,10 +1,29 @@
 use crate::handlers::*;
 use crate::{
-    error::ErrorOutput, models::chat::CreateChat, models::message::CreateMessage,
-    models::message::ListMessages, models::user::CreateUser, models::user::SigninUser, AppState,
+    error::ErrorOutput,
+    models::chat::{ChannelSummary, ChatListItem, CreateChat, ListChats, MessagePreview},
+    models::mention::ListMentions,
+    models::message::CreateMessage,
+    models::message::ListMessages,
+    models::message::{MessageEdit, MessagePage, Thread, UpdateMessage},
+    models::pin::{ChatDetail, PinnedMessage},
+    models::read::{ListReceipts, MarkRead},
+    models::scheduled::{CreateScheduledMessage, ScheduledMessage, UpdateScheduledMessage},
+    models::search::{SearchMessages, SearchResult},
+    models::session::RefreshToken,
+    models::session::Session,
+    models::user::CreateUser,
+    models::user::SigninUser,
+    models::workspace::{
+        AcceptInvite, CreateInvite, UpdateMemberRole, WorkspaceInvite, WorkspaceMember,
+    },
+    AppState,
 };
 use axum::Router;
-use chat_core::{Chat, ChatType, ChatUser, Message, User, Workspace};
+use chat_core::{
+    Chat, ChatType, ChatUser, Jwk, Jwks, Message, MessageReference, Pin, PinsUpdated,
+    ReactionChanged, ReactionCount, ReadMarker, ReferenceKind, User, Workspace, WorkspaceRole,
+};
 use utoipa::{
     openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
     Modify, OpenApi,
@@ -20,15 +39,53 @@ pub(crate) trait OpenApiRouter {
         paths(
             signup_handler,
             signin_handler,
+            refresh_handler,
+            signout_handler,
+            list_sessions_handler,
+            revoke_session_handler,
+            jwks_handler,
             list_chat_handler,
             create_chat_handler,
             get_chat_handler,
+            mark_read_handler,
+            list_receipts_handler,
+            list_pins_handler,
+            pin_message_handler,
+            unpin_message_handler,
+            delete_chat_handler,
+            archive_chat_handler,
+            unarchive_chat_handler,
+            list_channels_handler,
+            dm_handler,
+            join_chat_handler,
+            leave_chat_handler,
             list_messages_handler,
             send_message_handler,
+            update_message_handler,
+            delete_message_handler,
+            add_reaction_handler,
+            remove_reaction_handler,
+            list_message_edits_handler,
+            get_thread_handler,
+            search_handler,
+            list_mentions_handler,
+            create_scheduled_message_handler,
+            list_scheduled_messages_handler,
+            update_scheduled_message_handler,
+            cancel_scheduled_message_handler,
             list_chat_users_handler,
+            create_invite_handler,
+            list_invites_handler,
+            revoke_invite_handler,
+            accept_invite_handler,
+            join_invite_handler,
+            list_workspaces_handler,
+            switch_workspace_handler,
+            list_members_handler,
+            update_member_role_handler,
         ),
         components(
-            schemas(User, Chat, ChatType, ChatUser, Message, Workspace, SigninUser, CreateUser, CreateChat, CreateMessage, ListMessages, AuthOutput, ErrorOutput),
+            schemas(User, Chat, ChatType, ChatUser, Message, MessageReference, ReferenceKind, Workspace, SigninUser, CreateUser, CreateChat, ListChats, ChatListItem, MessagePreview, MarkRead, ListReceipts, ReadMarker, ChatDetail, PinnedMessage, Pin, PinsUpdated, ChannelSummary, CreateMessage, ListMessages, MessagePage, UpdateMessage, MessageEdit, Thread, SearchMessages, SearchResult, ListMentions, ScheduledMessage, CreateScheduledMessage, UpdateScheduledMessage, ReactionCount, ReactionChanged, AuthOutput, RefreshToken, Session, Jwks, Jwk, WorkspaceInvite, CreateInvite, AcceptInvite, WorkspaceMember, WorkspaceRole, UpdateMemberRole, ErrorOutput),
         ),
         modifiers(&SecurityAddon),
         tags(
@@ -47,7 +47,8 @@ async fn chat_server_should_work() -> Result<()> {
     let (tdb, state) = chat_server::AppState::new_for_test().await?;
     let chat_server = ChatServer::new(state).await?;
     let db_url = tdb.url();
-    NotifyServer::new(&db_url, &chat_server.token).await?;
+    let jwks_url = format!("http://{}/.well-known/jwks.json", chat_server.addr);
+    NotifyServer::new(&db_url, &jwks_url, &chat_server.token).await?;
     let chat = chat_server.create_chat().await?;
     let _msg = chat_server.create_message(chat.id as u64).await?;
     sleep(Duration::from_secs(1)).await;
@@ -55,9 +56,12 @@ async fn chat_server_should_work() -> Result<()> {
 }
 
 impl NotifyServer {
-    async fn new(db_url: &str, token: &str) -> Result<Self> {
+    async fn new(db_url: &str, jwks_url: &str, token: &str) -> Result<Self> {
         let mut config = notify_server::AppConfig::load()?;
         config.server.db_url = db_url.to_string();
+        // follow chat_server's keys instead of a configured public key
+        config.auth.pk = None;
+        config.auth.jwks_url = Some(jwks_url.to_string());
         let app = notify_server::get_router(config).await?;
         let listener = TcpListener::bind(WILD_ADDR).await?;
         let addr = listener.local_addr()?;
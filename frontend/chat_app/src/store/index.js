@@ -254,7 +254,7 @@ export default createStore({
               Authorization: `Bearer ${state.token}`,
             },
           });
-        let messages = response.data;
+        let messages = response.data.messages;
           // messages = messages.map((message) => {
           //   const user = state.users[message.senderId];
           //   return {
@@ -0,0 +1,14 @@
+-- refresh token sessions, access tokens carry the session id so they can be revoked
+CREATE TABLE IF NOT EXISTS sessions (
+  id bigserial PRIMARY KEY,
+  user_id bigint NOT NULL REFERENCES users(id),
+  -- sha256 of the refresh token, the token itself is never stored
+  refresh_token_hash char(64) NOT NULL UNIQUE,
+  user_agent text,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  refreshed_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  expires_at timestamptz NOT NULL,
+  revoked_at timestamptz
+);
+
+CREATE INDEX IF NOT EXISTS sessions_user_id_index ON sessions(user_id);
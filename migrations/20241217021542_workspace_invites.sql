@@ -0,0 +1,15 @@
+-- invite codes, signing up into an existing workspace requires one
+CREATE TABLE IF NOT EXISTS workspace_invites (
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  code varchar(32) NOT NULL UNIQUE,
+  created_by bigint NOT NULL REFERENCES users(id),
+  -- NULL means the invite can be used any number of times
+  max_uses integer,
+  uses integer NOT NULL DEFAULT 0,
+  expires_at timestamptz,
+  revoked_at timestamptz,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS workspace_invites_ws_id_index ON workspace_invites(ws_id);
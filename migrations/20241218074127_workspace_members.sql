@@ -0,0 +1,30 @@
+-- users can belong to several workspaces, users.ws_id is the one picked at signin
+CREATE TABLE IF NOT EXISTS workspace_members (
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  user_id bigint NOT NULL REFERENCES users(id),
+  joined_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (ws_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS workspace_members_user_id_index ON workspace_members(user_id);
+
+INSERT INTO workspace_members(ws_id, user_id)
+SELECT
+  ws_id,
+  id
+FROM
+  users
+ON CONFLICT DO NOTHING;
+
+-- the workspace an access token of the session is scoped to
+ALTER TABLE sessions
+  ADD COLUMN ws_id bigint REFERENCES workspaces(id);
+
+UPDATE
+  sessions s
+SET
+  ws_id = u.ws_id
+FROM
+  users u
+WHERE
+  s.user_id = u.id;
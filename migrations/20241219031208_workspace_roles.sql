@@ -0,0 +1,19 @@
+CREATE TYPE workspace_role AS ENUM(
+  'owner',
+  'admin',
+  'member',
+  'guest'
+);
+
+ALTER TABLE workspace_members
+  ADD COLUMN role workspace_role NOT NULL DEFAULT 'member';
+
+UPDATE
+  workspace_members m
+SET
+  role = 'owner'
+FROM
+  workspaces w
+WHERE
+  w.id = m.ws_id
+  AND w.owner_id = m.user_id;
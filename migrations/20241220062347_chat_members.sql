@@ -0,0 +1,181 @@
+-- chat membership moves out of chats.members into its own table
+CREATE TYPE chat_role AS ENUM(
+  'owner',
+  'admin',
+  'member'
+);
+
+CREATE TABLE IF NOT EXISTS chat_members(
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id),
+  role chat_role NOT NULL DEFAULT 'member',
+  joined_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  last_read_message_id bigint,
+  PRIMARY KEY (chat_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS chat_members_user_id_index ON chat_members(user_id);
+
+INSERT INTO chat_members(chat_id, user_id, joined_at)
+SELECT
+  id,
+  unnest(members),
+  created_at
+FROM
+  chats;
+
+-- drops the unique constraint and the GIN index on members as well
+ALTER TABLE chats
+  DROP COLUMN members;
+
+-- member ids of a chat, used wherever the api still returns chat.members
+CREATE OR REPLACE FUNCTION chat_member_ids(bigint)
+  RETURNS bigint[]
+  AS $$
+  SELECT
+    COALESCE(array_agg(user_id ORDER BY user_id), '{}')
+  FROM
+    chat_members
+  WHERE
+    chat_id = $1;
+$$
+LANGUAGE sql
+STABLE;
+
+-- chat row as sent in notifications, with the members added back
+CREATE OR REPLACE FUNCTION chat_to_json(chat chats, members bigint[])
+  RETURNS jsonb
+  AS $$
+  SELECT
+    to_jsonb(chat) || jsonb_build_object('members', members);
+$$
+LANGUAGE sql
+STABLE;
+
+CREATE OR REPLACE FUNCTION add_to_chat()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  members bigint[];
+BEGIN
+  RAISE NOTICE 'add_to_chat: % %', TG_OP, COALESCE(NEW.id, OLD.id);
+  IF TG_OP = 'DELETE' THEN
+    -- runs before the delete, members are gone once the cascade ran
+    PERFORM
+      pg_notify('chat_updated', json_build_object('op', TG_OP, 'old', chat_to_json(OLD, chat_member_ids(OLD.id)), 'new', NULL)::text);
+    RETURN OLD;
+  END IF;
+  members := chat_member_ids(NEW.id);
+  IF TG_OP = 'INSERT' THEN
+    PERFORM
+      pg_notify('chat_updated', json_build_object('op', TG_OP, 'old', NULL, 'new', chat_to_json(NEW, members))::text);
+  ELSE
+    PERFORM
+      pg_notify('chat_updated', json_build_object('op', TG_OP, 'old', chat_to_json(OLD, members), 'new', chat_to_json(NEW, members))::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS add_to_chat_trigger ON chats;
+
+-- new chats are announced at commit, once their members are inserted
+CREATE CONSTRAINT TRIGGER add_to_chat_insert_trigger
+  AFTER INSERT ON chats DEFERRABLE INITIALLY DEFERRED
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_chat();
+
+CREATE TRIGGER add_to_chat_update_trigger
+  AFTER UPDATE ON chats
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_chat();
+
+CREATE TRIGGER add_to_chat_delete_trigger
+  BEFORE DELETE ON chats
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_chat();
+
+-- if members joined or left a chat, notify with the old and new members
+CREATE OR REPLACE FUNCTION chat_members_changed()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  chat chats;
+  old_members bigint[];
+  new_members bigint[];
+BEGIN
+  FOR chat IN
+  SELECT
+    *
+  FROM
+    chats
+  WHERE
+    id IN (
+      SELECT
+        chat_id
+      FROM
+        changed)
+      LOOP
+        new_members := chat_member_ids(chat.id);
+        IF TG_OP = 'INSERT' THEN
+          SELECT
+            COALESCE(array_agg(m ORDER BY m), '{}') INTO old_members
+          FROM
+            unnest(new_members) m
+          WHERE
+            m NOT IN (
+              SELECT
+                user_id
+              FROM
+                changed
+              WHERE
+                chat_id = chat.id);
+          -- members of a new chat are part of its insert notification
+          CONTINUE
+          WHEN cardinality(old_members) = 0;
+        ELSE
+          SELECT
+            array_agg(m ORDER BY m) INTO old_members
+          FROM (
+            SELECT
+              unnest(new_members)
+            UNION
+            SELECT
+              user_id
+            FROM
+              changed
+            WHERE
+              chat_id = chat.id) t(m);
+        END IF;
+        PERFORM
+          pg_notify('chat_updated', json_build_object('op', 'UPDATE', 'old', chat_to_json(chat, old_members), 'new', chat_to_json(chat, new_members))::text);
+      END LOOP;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER chat_members_insert_trigger
+  AFTER INSERT ON chat_members REFERENCING NEW TABLE AS changed
+  FOR EACH STATEMENT
+  EXECUTE FUNCTION chat_members_changed();
+
+CREATE TRIGGER chat_members_delete_trigger
+  AFTER DELETE ON chat_members REFERENCING OLD TABLE AS changed
+  FOR EACH STATEMENT
+  EXECUTE FUNCTION chat_members_changed();
+
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    RAISE NOTICE 'add_to_message: %', NEW;
+    PERFORM
+      pg_notify('chat_message_created', json_build_object('message', NEW, 'members', chat_member_ids(NEW.chat_id))::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
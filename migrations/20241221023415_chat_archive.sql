@@ -0,0 +1,3 @@
+-- archived chats are hidden from the chat list but keep their history
+ALTER TABLE chats
+  ADD COLUMN archived_at timestamptz;
@@ -0,0 +1,38 @@
+-- senders can edit their messages, previous versions are kept
+ALTER TABLE messages
+  ADD COLUMN edited_at timestamptz;
+
+CREATE TABLE IF NOT EXISTS message_edits(
+  id bigserial PRIMARY KEY,
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  -- content before the edit
+  content text NOT NULL,
+  edited_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS message_edits_message_id_index ON message_edits(message_id);
+
+-- notify chat members about new and edited messages
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    RAISE NOTICE 'add_to_message: %', NEW;
+    PERFORM
+      pg_notify('chat_message_created', json_build_object('message', NEW, 'members', chat_member_ids(NEW.chat_id))::text);
+  ELSIF TG_OP = 'UPDATE' THEN
+    PERFORM
+      pg_notify('chat_message_updated', json_build_object('message', NEW, 'members', chat_member_ids(NEW.chat_id))::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS add_to_message_trigger ON messages;
+
+CREATE TRIGGER add_to_message_trigger
+  AFTER INSERT OR UPDATE ON messages
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_message();
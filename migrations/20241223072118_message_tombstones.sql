@@ -0,0 +1,23 @@
+-- deleted messages are kept as tombstones with their content cleared
+ALTER TABLE messages
+  ADD COLUMN deleted_at timestamptz;
+
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    RAISE NOTICE 'add_to_message: %', NEW;
+    PERFORM
+      pg_notify('chat_message_created', json_build_object('message', NEW, 'members', chat_member_ids(NEW.chat_id))::text);
+  ELSIF TG_OP = 'UPDATE' AND OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+    PERFORM
+      pg_notify('chat_message_deleted', json_build_object('message', NEW, 'members', chat_member_ids(NEW.chat_id))::text);
+  ELSIF TG_OP = 'UPDATE' THEN
+    PERFORM
+      pg_notify('chat_message_updated', json_build_object('message', NEW, 'members', chat_member_ids(NEW.chat_id))::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
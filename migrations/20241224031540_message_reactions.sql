@@ -0,0 +1,63 @@
+CREATE TABLE IF NOT EXISTS message_reactions(
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id),
+  emoji varchar(64) NOT NULL,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (message_id, emoji, user_id)
+);
+
+-- reactions of a message grouped by emoji, in the order they were first used
+CREATE OR REPLACE FUNCTION message_reactions_json(bigint)
+  RETURNS jsonb
+  AS $$
+  SELECT
+    COALESCE(jsonb_agg(jsonb_build_object('emoji', emoji, 'count', count, 'userIds', user_ids) ORDER BY first_at, emoji), '[]')
+  FROM (
+    SELECT
+      emoji,
+      count(*) AS count,
+      array_agg(user_id ORDER BY created_at, user_id) AS user_ids,
+      min(created_at) AS first_at
+    FROM
+      message_reactions
+    WHERE
+      message_id = $1
+    GROUP BY
+      emoji) r;
+$$
+LANGUAGE sql
+STABLE;
+
+CREATE OR REPLACE FUNCTION message_reaction_changed()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  reaction message_reactions;
+  cid bigint;
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    reaction := NEW;
+  ELSE
+    reaction := OLD;
+  END IF;
+  SELECT
+    chat_id INTO cid
+  FROM
+    messages
+  WHERE
+    id = reaction.message_id;
+  -- the message itself was deleted
+  IF NOT FOUND THEN
+    RETURN NULL;
+  END IF;
+  PERFORM
+    pg_notify('message_reaction_changed', json_build_object('reaction', json_build_object('messageId', reaction.message_id, 'chatId', cid, 'userId', reaction.user_id, 'emoji', reaction.emoji, 'added', TG_OP = 'INSERT', 'reactions', message_reactions_json(reaction.message_id)), 'members', chat_member_ids(cid))::text);
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_reaction_changed_trigger
+  AFTER INSERT OR DELETE ON message_reactions
+  FOR EACH ROW
+  EXECUTE FUNCTION message_reaction_changed();
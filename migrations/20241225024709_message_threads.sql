@@ -0,0 +1,32 @@
+-- replies to a message form its thread, threads are one level deep
+ALTER TABLE messages
+  ADD COLUMN parent_id bigint REFERENCES messages(id) ON DELETE CASCADE,
+  ADD COLUMN reply_count integer NOT NULL DEFAULT 0,
+  ADD COLUMN last_reply_at timestamptz;
+
+CREATE INDEX IF NOT EXISTS messages_parent_id_index ON messages(parent_id, id)
+WHERE
+  parent_id IS NOT NULL;
+
+-- keep the reply count of the parent up to date, members see it as an updated message
+CREATE OR REPLACE FUNCTION add_to_thread()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  UPDATE
+    messages
+  SET
+    reply_count = reply_count + 1,
+    last_reply_at = NEW.created_at
+  WHERE
+    id = NEW.parent_id;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER add_to_thread_trigger
+  AFTER INSERT ON messages
+  FOR EACH ROW
+  WHEN (NEW.parent_id IS NOT NULL)
+  EXECUTE FUNCTION add_to_thread();
@@ -0,0 +1,17 @@
+-- tell the other devices of a user when they read a chat
+CREATE OR REPLACE FUNCTION read_marker_updated()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  PERFORM
+    pg_notify('read_marker_updated', json_build_object('chatId', NEW.chat_id, 'userId', NEW.user_id, 'lastReadMessageId', NEW.last_read_message_id)::text);
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER read_marker_updated_trigger
+  AFTER UPDATE OF last_read_message_id ON chat_members
+  FOR EACH ROW
+  WHEN (OLD.last_read_message_id IS DISTINCT FROM NEW.last_read_message_id)
+  EXECUTE FUNCTION read_marker_updated();
@@ -0,0 +1,11 @@
+-- read markers are shared with the other members as read receipts
+CREATE OR REPLACE FUNCTION read_marker_updated()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  PERFORM
+    pg_notify('read_marker_updated', json_build_object('marker', json_build_object('chatId', NEW.chat_id, 'userId', NEW.user_id, 'lastReadMessageId', NEW.last_read_message_id), 'members', chat_member_ids(NEW.chat_id))::text);
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,60 @@
+-- the last message of a chat, to sort the chat list by activity
+ALTER TABLE chats
+  ADD COLUMN last_message_id bigint,
+  ADD COLUMN last_message_at timestamptz;
+
+CREATE INDEX IF NOT EXISTS chats_last_activity_index ON chats(COALESCE(last_message_at, created_at) DESC, id DESC);
+
+-- new messages only move the last message, members don't need a chat update for it
+DROP TRIGGER IF EXISTS add_to_chat_update_trigger ON chats;
+
+CREATE TRIGGER add_to_chat_update_trigger
+  AFTER UPDATE ON chats
+  FOR EACH ROW
+  WHEN (OLD.last_message_id IS NOT DISTINCT FROM NEW.last_message_id)
+  EXECUTE FUNCTION add_to_chat();
+
+UPDATE
+  chats c
+SET
+  last_message_id = m.id,
+  last_message_at = m.created_at
+FROM (
+  SELECT DISTINCT ON (chat_id)
+    chat_id,
+    id,
+    created_at
+  FROM
+    messages
+  ORDER BY
+    chat_id,
+    id DESC) m
+WHERE
+  m.chat_id = c.id;
+
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    RAISE NOTICE 'add_to_message: %', NEW;
+    UPDATE
+      chats
+    SET
+      last_message_id = NEW.id,
+      last_message_at = NEW.created_at
+    WHERE
+      id = NEW.chat_id;
+    PERFORM
+      pg_notify('chat_message_created', json_build_object('message', NEW, 'members', chat_member_ids(NEW.chat_id))::text);
+  ELSIF TG_OP = 'UPDATE' AND OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+    PERFORM
+      pg_notify('chat_message_deleted', json_build_object('message', NEW, 'members', chat_member_ids(NEW.chat_id))::text);
+  ELSIF TG_OP = 'UPDATE' THEN
+    PERFORM
+      pg_notify('chat_message_updated', json_build_object('message', NEW, 'members', chat_member_ids(NEW.chat_id))::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,27 @@
+-- one single chat per pair of users, keyed by the sorted ids of the two members
+ALTER TABLE chats
+  ADD COLUMN dm_key text;
+
+UPDATE
+  chats c
+SET
+  dm_key = k.dm_key
+FROM (
+  SELECT DISTINCT ON (c.ws_id, k.dm_key)
+    c.id,
+    k.dm_key
+  FROM
+    chats c
+    CROSS JOIN LATERAL (
+      SELECT
+        array_to_string(chat_member_ids(c.id), ':') AS dm_key) k
+  WHERE
+    c.chat_type = 'single'
+  ORDER BY
+    c.ws_id,
+    k.dm_key,
+    c.id) k
+WHERE
+  k.id = c.id;
+
+CREATE UNIQUE INDEX IF NOT EXISTS chats_dm_key_index ON chats(ws_id, dm_key);
@@ -0,0 +1,45 @@
+-- full text search over message content
+ALTER TABLE messages
+  ADD COLUMN content_tsv tsvector GENERATED ALWAYS AS (to_tsvector('simple', content)) STORED;
+
+CREATE INDEX IF NOT EXISTS messages_content_tsv_index ON messages USING GIN(content_tsv);
+
+-- html escaped text, search snippets are highlighted with <mark>
+CREATE OR REPLACE FUNCTION html_escape(text)
+  RETURNS text
+  AS $$
+  SELECT
+    replace(replace(replace($1, '&', '&amp;'), '<', '&lt;'), '>', '&gt;');
+$$
+LANGUAGE sql
+IMMUTABLE;
+
+-- the search vector is left out of notifications, they are limited to 8000 bytes
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  message jsonb := to_jsonb(NEW) - 'content_tsv';
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    RAISE NOTICE 'add_to_message: %', message;
+    UPDATE
+      chats
+    SET
+      last_message_id = NEW.id,
+      last_message_at = NEW.created_at
+    WHERE
+      id = NEW.chat_id;
+    PERFORM
+      pg_notify('chat_message_created', json_build_object('message', message, 'members', chat_member_ids(NEW.chat_id))::text);
+  ELSIF TG_OP = 'UPDATE' AND OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+    PERFORM
+      pg_notify('chat_message_deleted', json_build_object('message', message, 'members', chat_member_ids(NEW.chat_id))::text);
+  ELSIF TG_OP = 'UPDATE' THEN
+    PERFORM
+      pg_notify('chat_message_updated', json_build_object('message', message, 'members', chat_member_ids(NEW.chat_id))::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
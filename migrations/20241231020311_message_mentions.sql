@@ -0,0 +1,41 @@
+-- users mentioned in a message, @here and @channel mention every member of the chat
+CREATE TABLE IF NOT EXISTS message_mentions(
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (message_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS message_mentions_user_id_index ON message_mentions(user_id, message_id DESC);
+
+-- one notification per message, sent to the mentioned users only
+CREATE OR REPLACE FUNCTION message_mentioned()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  r record;
+BEGIN
+  FOR r IN
+  SELECT
+    message_id,
+    array_agg(user_id ORDER BY user_id) AS user_ids
+  FROM
+    mentioned
+  GROUP BY
+    message_id LOOP
+      PERFORM
+        pg_notify('message_mentioned', json_build_object('message', to_jsonb(m) - 'content_tsv', 'members', r.user_ids)::text)
+      FROM
+        messages m
+      WHERE
+        m.id = r.message_id;
+    END LOOP;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_mentioned_trigger
+  AFTER INSERT ON message_mentions REFERENCING NEW TABLE AS mentioned
+  FOR EACH STATEMENT
+  EXECUTE FUNCTION message_mentioned();
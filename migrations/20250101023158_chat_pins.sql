@@ -0,0 +1,52 @@
+-- messages pinned to the top of a chat
+CREATE TABLE IF NOT EXISTS chat_pins(
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  pinned_by bigint NOT NULL REFERENCES users(id),
+  pinned_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (chat_id, message_id)
+);
+
+-- pins of a chat as sent in notifications, oldest first
+CREATE OR REPLACE FUNCTION chat_pins_json(bigint)
+  RETURNS jsonb
+  AS $$
+  SELECT
+    COALESCE(jsonb_agg(jsonb_build_object('chatId', chat_id, 'messageId', message_id, 'pinnedBy', pinned_by, 'pinnedAt', pinned_at) ORDER BY pinned_at, message_id), '[]')
+  FROM
+    chat_pins
+  WHERE
+    chat_id = $1;
+$$
+LANGUAGE sql
+STABLE;
+
+CREATE OR REPLACE FUNCTION chat_pins_changed()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  id bigint;
+BEGIN
+  -- chats being purged are gone already
+  FOR id IN SELECT DISTINCT
+    p.chat_id
+  FROM
+    changed p
+    JOIN chats c ON c.id = p.chat_id LOOP
+      PERFORM
+        pg_notify('chat_pins_updated', json_build_object('pins', json_build_object('chatId', id, 'pins', chat_pins_json(id)), 'members', chat_member_ids(id))::text);
+    END LOOP;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER chat_pins_insert_trigger
+  AFTER INSERT ON chat_pins REFERENCING NEW TABLE AS changed
+  FOR EACH STATEMENT
+  EXECUTE FUNCTION chat_pins_changed();
+
+CREATE TRIGGER chat_pins_delete_trigger
+  AFTER DELETE ON chat_pins REFERENCING OLD TABLE AS changed
+  FOR EACH STATEMENT
+  EXECUTE FUNCTION chat_pins_changed();
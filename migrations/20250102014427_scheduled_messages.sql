@@ -0,0 +1,22 @@
+-- messages sent later by the scheduler in chat_server
+CREATE TABLE IF NOT EXISTS scheduled_messages(
+  id bigserial PRIMARY KEY,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  sender_id bigint NOT NULL REFERENCES users(id),
+  content text NOT NULL,
+  files text[] NOT NULL DEFAULT '{}',
+  send_at timestamptz NOT NULL,
+  -- the timezone send_at was given in, to show and edit it as local time
+  timezone text NOT NULL DEFAULT 'UTC',
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  -- set once delivered, or the reason the delivery failed
+  message_id bigint REFERENCES messages(id) ON DELETE SET NULL,
+  sent_at timestamptz,
+  error text
+);
+
+CREATE INDEX IF NOT EXISTS scheduled_messages_due_index ON scheduled_messages(send_at)
+WHERE
+  sent_at IS NULL AND error IS NULL;
+
+CREATE INDEX IF NOT EXISTS scheduled_messages_sender_id_index ON scheduled_messages(sender_id);
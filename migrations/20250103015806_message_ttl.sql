@@ -0,0 +1,41 @@
+-- self-destructing messages, per message or as a default of the chat
+ALTER TABLE messages
+  ADD COLUMN expires_at timestamptz;
+
+ALTER TABLE chats
+  ADD COLUMN message_ttl_secs integer CHECK (message_ttl_secs > 0);
+
+CREATE INDEX IF NOT EXISTS messages_expires_at_index ON messages(expires_at)
+WHERE
+  expires_at IS NOT NULL AND deleted_at IS NULL;
+
+-- messages as the api reads them, expired messages are tombstones before the reaper runs
+CREATE OR REPLACE VIEW visible_messages AS
+SELECT
+  id,
+  chat_id,
+  sender_id,
+  CASE WHEN expires_at <= now() THEN
+    ''
+  ELSE
+    content
+  END AS content,
+  CASE WHEN expires_at <= now() THEN
+    '{}'
+  ELSE
+    files
+  END AS files,
+  created_at,
+  edited_at,
+  CASE WHEN deleted_at IS NULL AND expires_at <= now() THEN
+    expires_at
+  ELSE
+    deleted_at
+  END AS deleted_at,
+  parent_id,
+  reply_count,
+  last_reply_at,
+  expires_at,
+  content_tsv
+FROM
+  messages;
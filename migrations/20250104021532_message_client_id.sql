@@ -0,0 +1,7 @@
+-- nonce generated by the client, a retried send returns the message created first
+ALTER TABLE messages
+  ADD COLUMN client_id varchar(64);
+
+CREATE UNIQUE INDEX IF NOT EXISTS messages_sender_client_id_index ON messages(sender_id, client_id)
+WHERE
+  client_id IS NOT NULL;
@@ -0,0 +1,71 @@
+-- quoted or forwarded message, a snapshot so it survives edits and deletion of the original
+ALTER TABLE messages
+  ADD COLUMN reference jsonb;
+
+-- the api decodes a missing reference from json null
+CREATE OR REPLACE VIEW visible_messages AS
+SELECT
+  id,
+  chat_id,
+  sender_id,
+  CASE WHEN expires_at <= now() THEN
+    ''
+  ELSE
+    content
+  END AS content,
+  CASE WHEN expires_at <= now() THEN
+    '{}'
+  ELSE
+    files
+  END AS files,
+  created_at,
+  edited_at,
+  CASE WHEN deleted_at IS NULL AND expires_at <= now() THEN
+    expires_at
+  ELSE
+    deleted_at
+  END AS deleted_at,
+  parent_id,
+  reply_count,
+  last_reply_at,
+  expires_at,
+  content_tsv,
+  CASE WHEN expires_at <= now() THEN
+    'null'
+  ELSE
+    COALESCE(reference, 'null')
+  END AS reference
+FROM
+  messages;
+
+-- files of messages and of the snapshots they reference, files in use are never removed
+CREATE OR REPLACE FUNCTION message_files_in_use(text[])
+  RETURNS SETOF text
+  AS $$
+  SELECT
+    f
+  FROM
+    messages,
+    unnest(files) f
+  WHERE
+    f = ANY ($1)
+  UNION
+  SELECT
+    f
+  FROM
+    messages,
+    jsonb_array_elements_text(reference -> 'files') f
+  WHERE
+    reference IS NOT NULL
+    AND f = ANY ($1)
+  UNION
+  SELECT
+    f
+  FROM
+    scheduled_messages,
+    unnest(files) f
+  WHERE
+    f = ANY ($1);
+$$
+LANGUAGE sql
+STABLE;
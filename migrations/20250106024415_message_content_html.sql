@@ -0,0 +1,57 @@
+-- sanitized html of the markdown content, rendered by the server when a message is sent
+ALTER TABLE messages
+  ADD COLUMN content_html text;
+
+-- older messages are shown as escaped plain text
+CREATE OR REPLACE FUNCTION plain_text_html(text)
+  RETURNS text
+  AS $$
+  SELECT
+    CASE WHEN $1 = '' THEN
+      ''
+    ELSE
+      '<p>' || replace(replace(html_escape($1), '"', '&quot;'), E'\n', '<br>') || '</p>'
+    END;
+$$
+LANGUAGE sql
+IMMUTABLE;
+
+CREATE OR REPLACE VIEW visible_messages AS
+SELECT
+  id,
+  chat_id,
+  sender_id,
+  CASE WHEN expires_at <= now() THEN
+    ''
+  ELSE
+    content
+  END AS content,
+  CASE WHEN expires_at <= now() THEN
+    '{}'
+  ELSE
+    files
+  END AS files,
+  created_at,
+  edited_at,
+  CASE WHEN deleted_at IS NULL AND expires_at <= now() THEN
+    expires_at
+  ELSE
+    deleted_at
+  END AS deleted_at,
+  parent_id,
+  reply_count,
+  last_reply_at,
+  expires_at,
+  content_tsv,
+  CASE WHEN expires_at <= now() THEN
+    'null'
+  ELSE
+    COALESCE(reference, 'null')
+  END AS reference,
+  CASE WHEN expires_at <= now() THEN
+    ''
+  ELSE
+    COALESCE(content_html, plain_text_html(content))
+  END AS content_html
+FROM
+  messages;
@@ -0,0 +1,50 @@
+-- message notifications carry the message id only and notify_server loads the message,
+-- content, html and quoted snapshots don't fit in the 8000 bytes of a notification
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    RAISE NOTICE 'add_to_message: %', NEW.id;
+    UPDATE
+      chats
+    SET
+      last_message_id = NEW.id,
+      last_message_at = NEW.created_at
+    WHERE
+      id = NEW.chat_id;
+    PERFORM
+      pg_notify('chat_message_created', json_build_object('messageId', NEW.id, 'members', chat_member_ids(NEW.chat_id))::text);
+  ELSIF TG_OP = 'UPDATE' AND OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+    PERFORM
+      pg_notify('chat_message_deleted', json_build_object('messageId', NEW.id, 'members', chat_member_ids(NEW.chat_id))::text);
+  ELSIF TG_OP = 'UPDATE' THEN
+    PERFORM
+      pg_notify('chat_message_updated', json_build_object('messageId', NEW.id, 'members', chat_member_ids(NEW.chat_id))::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION message_mentioned()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  r record;
+BEGIN
+  FOR r IN
+  SELECT
+    message_id,
+    array_agg(user_id ORDER BY user_id) AS user_ids
+  FROM
+    mentioned
+  GROUP BY
+    message_id LOOP
+      PERFORM
+        pg_notify('message_mentioned', json_build_object('messageId', r.message_id, 'members', r.user_ids)::text);
+    END LOOP;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,7 @@
+-- messages sent before rendering are shown as escaped plain text
+UPDATE
+  messages
+SET
+  content_html = plain_text_html(content)
+WHERE
+  content_html IS NULL;
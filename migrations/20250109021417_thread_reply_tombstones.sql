@@ -0,0 +1,51 @@
+-- deleted or expired replies are tombstones, the thread doesn't count them
+CREATE OR REPLACE FUNCTION remove_from_thread()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  UPDATE
+    messages
+  SET
+    reply_count = r.reply_count,
+    last_reply_at = r.last_reply_at
+  FROM (
+    SELECT
+      count(*) AS reply_count,
+      max(created_at) AS last_reply_at
+    FROM
+      messages
+    WHERE
+      parent_id = NEW.parent_id
+      AND deleted_at IS NULL) r
+WHERE
+  id = NEW.parent_id;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER remove_from_thread_trigger
+  AFTER UPDATE OF deleted_at ON messages
+  FOR EACH ROW
+  WHEN (NEW.parent_id IS NOT NULL AND OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL)
+  EXECUTE FUNCTION remove_from_thread();
+
+UPDATE
+  messages p
+SET
+  reply_count = r.reply_count,
+  last_reply_at = r.last_reply_at
+FROM (
+  SELECT
+    parent_id,
+    count(*) FILTER (WHERE deleted_at IS NULL) AS reply_count,
+    max(created_at) FILTER (WHERE deleted_at IS NULL) AS last_reply_at
+  FROM
+    messages
+  WHERE
+    parent_id IS NOT NULL
+  GROUP BY
+    parent_id) r
+WHERE
+  p.id = r.parent_id
+  AND p.reply_count <> r.reply_count;
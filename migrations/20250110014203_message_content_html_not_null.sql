@@ -0,0 +1,50 @@
+-- every message has its html since the backfill, the view no longer renders a fallback
+UPDATE
+  messages
+SET
+  content_html = plain_text_html(content)
+WHERE
+  content_html IS NULL;
+
+ALTER TABLE messages
+  ALTER COLUMN content_html SET NOT NULL;
+
+CREATE OR REPLACE VIEW visible_messages AS
+SELECT
+  id,
+  chat_id,
+  sender_id,
+  CASE WHEN expires_at <= now() THEN
+    ''
+  ELSE
+    content
+  END AS content,
+  CASE WHEN expires_at <= now() THEN
+    '{}'
+  ELSE
+    files
+  END AS files,
+  created_at,
+  edited_at,
+  CASE WHEN deleted_at IS NULL AND expires_at <= now() THEN
+    expires_at
+  ELSE
+    deleted_at
+  END AS deleted_at,
+  parent_id,
+  reply_count,
+  last_reply_at,
+  expires_at,
+  content_tsv,
+  CASE WHEN expires_at <= now() THEN
+    'null'
+  ELSE
+    COALESCE(reference, 'null')
+  END AS reference,
+  CASE WHEN expires_at <= now() THEN
+    ''
+  ELSE
+    content_html
+  END AS content_html
+FROM
+  messages;
@@ -1,4 +1,4 @@
-use std::{env, fs::File};
+use std::{env, fs::File, path::PathBuf};
 
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
@@ -9,15 +9,28 @@ pub struct AppConfig {
     pub auth: AuthConfig,
 }
 
+/// Where to find the public keys for token verification, at least one source is required.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthConfig {
-    pub pk: String,
+    #[serde(default)]
+    pub pk: Option<String>,
+    #[serde(default)]
+    pub kid: Option<String>,
+    /// chat_server's `/.well-known/jwks.json`
+    #[serde(default)]
+    pub jwks_url: Option<String>,
+    /// a local file in jwks format
+    #[serde(default)]
+    pub jwks_file: Option<PathBuf>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub port: u16,
     pub db_url: String,
+    /// chats with more members don't share read receipts
+    #[serde(default = "default_receipts_max_members")]
+    pub receipts_max_members: usize,
 }
 
 impl AppConfig {
@@ -36,3 +49,7 @@ impl AppConfig {
         Ok(ret?)
     }
 }
+
+fn default_receipts_max_members() -> usize {
+    50
+}
@@ -13,6 +13,10 @@ pub enum AppError {
     IoError(#[from] std::io::Error),
     #[error("jwt error: {0}")]
     JwtError(#[from] jwt_simple::Error),
+    #[error("sqlx error: {0}")]
+    SqlxError(#[from] sqlx::Error),
+    #[error("session has been revoked")]
+    SessionRevoked,
 }
 impl ErrorOutput {
     pub fn new(error: impl Into<String>) -> Self {
@@ -26,6 +30,8 @@ impl IntoResponse for AppError {
         let status = match &self {
             Self::JwtError(_) => StatusCode::FORBIDDEN,
             Self::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::SqlxError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::SessionRevoked => StatusCode::FORBIDDEN,
         };
         (status, Json(ErrorOutput::new(self.to_string()))).into_response()
     }
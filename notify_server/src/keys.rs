@@ -0,0 +1,161 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use chat_core::{DecodingKey, Jwks};
+use jwt_simple::reexports::serde_json;
+use tracing::{info, warn};
+
+use crate::{config::AuthConfig, AppState};
+
+// pick up keys added on chat_server even if no token needs them yet
+const KEY_RELOAD_INTERVAL: Duration = Duration::from_secs(300);
+// a token signed by an unknown key triggers a reload, but not more often than this
+const KEY_RELOAD_MIN_INTERVAL: Duration = Duration::from_secs(10);
+
+impl AuthConfig {
+    pub async fn load_decoding_key(&self) -> Result<DecodingKey> {
+        let mut dk = DecodingKey::default();
+        if let Some(pk) = &self.pk {
+            dk.add_pem(pk, self.kid.as_deref())?;
+        }
+        if let Some(path) = &self.jwks_file {
+            let jwks: Jwks = serde_json::from_slice(&tokio::fs::read(path).await?)?;
+            dk.add_jwks(&jwks)?;
+        }
+        if let Some(url) = &self.jwks_url {
+            let jwks: Jwks = reqwest::get(url).await?.error_for_status()?.json().await?;
+            dk.add_jwks(&jwks)?;
+        }
+        if dk.is_empty() {
+            bail!("no verification keys found, set auth.pk, auth.jwks_url or auth.jwks_file");
+        }
+        Ok(dk)
+    }
+
+    fn is_reloadable(&self) -> bool {
+        self.jwks_url.is_some() || self.jwks_file.is_some()
+    }
+}
+
+impl AppState {
+    pub(crate) fn decoding_key(&self) -> Arc<DecodingKey> {
+        self.dk.read().expect("dk lock poisoned").clone()
+    }
+
+    pub(crate) async fn reload_keys(&self) -> Result<()> {
+        if !self.config.auth.is_reloadable() {
+            return Ok(());
+        }
+        {
+            let mut loaded_at = self.dk_loaded_at.lock().expect("dk lock poisoned");
+            if loaded_at.elapsed() < KEY_RELOAD_MIN_INTERVAL {
+                return Ok(());
+            }
+            *loaded_at = Instant::now();
+        }
+        let dk = self.config.auth.load_decoding_key().await?;
+        info!("Reloaded {} verification keys", dk.to_jwks().keys.len());
+        *self.dk.write().expect("dk lock poisoned") = Arc::new(dk);
+        Ok(())
+    }
+}
+
+pub(crate) fn setup_key_reloader(state: AppState) {
+    if !state.config.auth.is_reloadable() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(KEY_RELOAD_INTERVAL);
+        // the first tick completes immediately, keys were just loaded
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = state.reload_keys().await {
+                warn!("Failed to reload verification keys: {:?}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::AppError, AppConfig};
+    use chat_core::{middlewares::TokenVerify, EncodingKey, User};
+    use std::path::Path;
+
+    const ENCODING_PEM: &str = include_str!("../../chat_core/fixtures/encoding.pem");
+    const DECODING_PEM: &str = include_str!("../../chat_core/fixtures/decoding.pem");
+
+    fn write_jwks(path: &Path, kids: &[&str]) -> Result<()> {
+        let mut dk = DecodingKey::default();
+        for kid in kids {
+            dk.add_pem(DECODING_PEM, Some(kid))?;
+        }
+        std::fs::write(path, serde_json::to_vec(&dk.to_jwks())?)?;
+        Ok(())
+    }
+
+    fn sign_token(kid: &str) -> Result<String> {
+        let user: User = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "username": "Ivena",
+            "wsId": 1,
+            "wsName": "acme",
+            "email": "test2@acme.org",
+            "createdAt": "2025-01-01T00:00:00Z",
+        }))?;
+        EncodingKey::load(ENCODING_PEM)?.with_kid(kid).sign(user)
+    }
+
+    #[tokio::test]
+    async fn unknown_kid_should_reload_keys_at_most_every_interval() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("notify_jwks_{}.json", std::process::id()));
+        write_jwks(&path, &["2024-12"])?;
+        let mut config = AppConfig::load()?;
+        config.auth.pk = None;
+        config.auth.jwks_file = Some(path.clone());
+        let state = AppState::try_new(config).await?;
+        let token = sign_token("2025-01")?;
+
+        // chat_server rotated right after the keys were loaded, too early for a reload
+        write_jwks(&path, &["2024-12", "2025-01"])?;
+        let ret = state.verify(&token).await;
+        assert!(matches!(ret, Err(AppError::JwtError(_))));
+        assert!(!state.decoding_key().has_key_for(&token));
+
+        let earlier = Instant::now()
+            .checked_sub(KEY_RELOAD_MIN_INTERVAL)
+            .expect("instant should be after the interval");
+        *state.dk_loaded_at.lock().expect("dk lock poisoned") = earlier;
+        // the signature is valid now, the token just has no session
+        let ret = state.verify(&token).await;
+        assert!(matches!(ret, Err(AppError::SessionRevoked)));
+        assert!(state.decoding_key().has_key_for(&token));
+        assert!(state.decoding_key().has_key_for(&sign_token("2024-12")?));
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn configured_key_should_not_reload() -> Result<()> {
+        let config = AppConfig::load()?;
+        let state = AppState::try_new(config).await?;
+        let earlier = Instant::now()
+            .checked_sub(KEY_RELOAD_MIN_INTERVAL)
+            .expect("instant should be after the interval");
+        *state.dk_loaded_at.lock().expect("dk lock poisoned") = earlier;
+        let ret = state.verify(&sign_token("2025-01")?).await;
+        assert!(matches!(ret, Err(AppError::JwtError(_))));
+        // no reload happened
+        assert_eq!(
+            *state.dk_loaded_at.lock().expect("dk lock poisoned"),
+            earlier
+        );
+        Ok(())
+    }
+}
@@ -1,8 +1,13 @@
 pub mod config;
 mod error;
+mod keys;
 pub mod notify;
 mod sse;
-use std::{ops::Deref, sync::Arc};
+use std::{
+    ops::Deref,
+    sync::{Arc, Mutex, RwLock},
+    time::Instant,
+};
 
 use axum::{
     http::Method,
@@ -18,9 +23,11 @@ pub use config::AppConfig;
 use dashmap::DashMap;
 use error::AppError;
 pub use notify::*;
+use sqlx::PgPool;
 use sse::{sse_handler, AppEvent};
 use tokio::sync::broadcast;
 use tower_http::cors::{self, CorsLayer};
+use tracing::warn;
 const INDEX_HTML: &str = include_str!("../index.html");
 pub type UserMap = Arc<DashMap<u64, broadcast::Sender<Arc<AppEvent>>>>;
 
@@ -28,16 +35,51 @@ pub type UserMap = Arc<DashMap<u64, broadcast::Sender<Arc<AppEvent>>>>;
 pub struct AppState(Arc<AppStateInner>);
 
 impl AppState {
-    pub fn new(config: AppConfig) -> Self {
-        let dk = DecodingKey::load(&config.auth.pk).expect("Failed to load decoding key");
+    pub async fn try_new(config: AppConfig) -> anyhow::Result<Self> {
+        let dk = config.auth.load_decoding_key().await?;
+        let pool = PgPool::connect_lazy(&config.server.db_url)?;
         let users = Arc::new(DashMap::new());
-        Self(Arc::new(AppStateInner { config, users, dk }))
+        Ok(Self(Arc::new(AppStateInner {
+            config,
+            users,
+            dk: RwLock::new(Arc::new(dk)),
+            dk_loaded_at: Mutex::new(Instant::now()),
+            pool,
+        })))
     }
 }
 impl TokenVerify for AppState {
     type Error = AppError;
-    fn verify(&self, token: &str) -> Result<User, Self::Error> {
-        Ok(self.dk.verify(token)?)
+    async fn verify(&self, token: &str) -> Result<User, Self::Error> {
+        let mut dk = self.decoding_key();
+        if !dk.has_key_for(token) {
+            // chat_server may have rotated to a key we haven't seen yet
+            if let Err(e) = self.reload_keys().await {
+                warn!("Failed to reload verification keys: {:?}", e);
+            }
+            dk = self.decoding_key();
+        }
+        let user = dk.verify(token)?;
+        // reject tokens whose session was revoked on chat_server
+        let active = match user.sid {
+            Some(sid) => sqlx::query(
+                r#"
+                SELECT 1
+                FROM sessions
+                WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL AND expires_at > now()
+                "#,
+            )
+            .bind(sid)
+            .bind(user.id)
+            .fetch_optional(&self.pool)
+            .await?
+            .is_some(),
+            None => false,
+        };
+        if !active {
+            return Err(AppError::SessionRevoked);
+        }
+        Ok(user)
     }
 }
 impl Deref for AppState {
@@ -50,12 +92,15 @@ impl Deref for AppState {
 pub struct AppStateInner {
     pub config: AppConfig,
     users: UserMap,
-    dk: DecodingKey,
+    dk: RwLock<Arc<DecodingKey>>,
+    dk_loaded_at: Mutex<Instant>,
+    pool: PgPool,
 }
 
 pub async fn get_router(config: AppConfig) -> anyhow::Result<Router> {
-    let state = AppState::new(config);
+    let state = AppState::try_new(config).await?;
     notify::setup_pg_listener(state.clone()).await?;
+    keys::setup_key_reloader(state.clone());
     let cors = CorsLayer::new()
         // allow `GET` and `POST` when accessing the resource
         .allow_methods([
@@ -80,3 +125,29 @@ pub async fn get_router(config: AppConfig) -> anyhow::Result<Router> {
 async fn index_handler() -> impl IntoResponse {
     Html(INDEX_HTML)
 }
+
+#[cfg(test)]
+mod test_util {
+    use super::*;
+    use sqlx_db_tester::TestPg;
+    use std::path::Path;
+
+    impl AppState {
+        /// A state on a fresh database with chat_server's migrations and test data.
+        pub(crate) async fn new_for_test() -> anyhow::Result<(TestPg, Self)> {
+            let mut config = AppConfig::load()?;
+            let post = config.server.db_url.rfind('/').expect("invalid db_url");
+            let tdb = TestPg::new(
+                config.server.db_url[..post].to_string(),
+                Path::new("../migrations"),
+            );
+            let pool = tdb.get_pool().await;
+            sqlx::raw_sql(include_str!("../../chat_server/fixtures/test.sql"))
+                .execute(&pool)
+                .await?;
+            config.server.db_url = tdb.url();
+            let state = Self::try_new(config).await?;
+            Ok((tdb, state))
+        }
+    }
+}
@@ -1,8 +1,8 @@
-use chat_core::{Chat, Message};
+use chat_core::{Chat, Message, PinsUpdated, ReactionChanged, ReadMarker};
 use futures::StreamExt;
 use jwt_simple::reexports::serde_json;
 use serde::{Deserialize, Serialize};
-use sqlx::postgres::PgListener;
+use sqlx::{postgres::PgListener, PgPool};
 use std::{collections::HashSet, sync::Arc};
 use tokio::time::{self};
 use tracing::{info, warn};
@@ -24,68 +24,160 @@ struct ChatUpdated {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct ChatMessageCreated {
-    message: Message,
+struct MessageReactionChanged {
+    reaction: ReactionChanged,
+    members: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatPinsUpdated {
+    pins: PinsUpdated,
+    members: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ReadMarkerUpdated {
+    marker: ReadMarker,
+    members: Vec<i64>,
+}
+
+/// Messages don't fit in a notification, they are loaded by id.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChatMessageChanged {
+    message_id: i64,
     members: Vec<i64>,
 }
 
 impl Notification {
-    fn load(r#type: &str, payload: &str) -> anyhow::Result<Self> {
+    fn new(user_ids: HashSet<u64>, event: AppEvent) -> Self {
+        Self {
+            user_ids,
+            event: Arc::new(event),
+        }
+    }
+
+    /// A notification can fan out into several events, e.g. users removed from a chat
+    /// get a different event than the ones who stay.
+    async fn load(r#type: &str, payload: &str, state: &AppState) -> anyhow::Result<Vec<Self>> {
         match r#type {
             "chat_updated" => {
                 let payload: ChatUpdated = serde_json::from_str(payload)?;
                 info!("ChatUpdated: {:?}", payload);
-                let user_ids =
-                    get_affected_chat_user_ids(payload.old.as_ref(), payload.new.as_ref());
-                let event = match payload.op.as_str() {
-                    "INSERT" => AppEvent::NewChat(payload.new.expect("new should exist")),
-                    "UPDATE" => {
-                        let old = payload.old.as_ref().expect("old should exist");
-                        let new = payload.new.as_ref().expect("new should exist");
-                        // check if the chat name was updated
-                        if old.members == new.members && old.name != new.name {
-                            AppEvent::ChatNameUpdated(payload.new.expect("new should exist"))
-                        } else {
-                            AppEvent::AddToChat(payload.new.expect("new should exist"))
-                        }
+                let ret = match (payload.op.as_str(), payload.old, payload.new) {
+                    ("INSERT", _, Some(new)) => {
+                        vec![Self::new(member_ids(&new), AppEvent::NewChat(new))]
+                    }
+                    ("UPDATE", Some(old), Some(new)) => get_chat_update_notifications(old, new),
+                    ("DELETE", Some(old), _) => {
+                        vec![Self::new(member_ids(&old), AppEvent::RemoveFromChat(old))]
                     }
-                    "DELETE" => AppEvent::RemoveFromChat(payload.old.expect("old should exist")),
                     _ => return Err(anyhow::anyhow!("Invalid operation")),
                 };
-                Ok(Self {
+                Ok(ret)
+            }
+            "chat_message_created" | "chat_message_updated" | "chat_message_deleted" => {
+                let payload: ChatMessageChanged = serde_json::from_str(payload)?;
+                // the chat may have been purged in the meantime
+                let Some(message) = load_message(&state.pool, payload.message_id).await? else {
+                    return Ok(vec![]);
+                };
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                let event = match r#type {
+                    "chat_message_created" => AppEvent::NewMessage(message),
+                    "chat_message_deleted" => AppEvent::MessageDeleted(message),
+                    _ => AppEvent::MessageUpdated(message),
+                };
+                Ok(vec![Self::new(user_ids, event)])
+            }
+            "chat_pins_updated" => {
+                let payload: ChatPinsUpdated = serde_json::from_str(payload)?;
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                Ok(vec![Self::new(
                     user_ids,
-                    event: Arc::new(event),
-                })
+                    AppEvent::PinsUpdated(payload.pins),
+                )])
             }
-            "chat_message_created" => {
-                let payload: ChatMessageCreated = serde_json::from_str(payload)?;
+            "message_mentioned" => {
+                // members are the mentioned users here
+                let payload: ChatMessageChanged = serde_json::from_str(payload)?;
+                let Some(message) = load_message(&state.pool, payload.message_id).await? else {
+                    return Ok(vec![]);
+                };
                 let user_ids = payload.members.iter().map(|v| *v as u64).collect();
-                Ok(Self {
+                Ok(vec![Self::new(user_ids, AppEvent::Mentioned(message))])
+            }
+            "message_reaction_changed" => {
+                let payload: MessageReactionChanged = serde_json::from_str(payload)?;
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                Ok(vec![Self::new(
                     user_ids,
-                    event: Arc::new(AppEvent::NewMessage(payload.message)),
-                })
+                    AppEvent::ReactionChanged(payload.reaction),
+                )])
+            }
+            "read_marker_updated" => {
+                let payload: ReadMarkerUpdated = serde_json::from_str(payload)?;
+                let marker = payload.marker;
+                // large chats opt out of read receipts, only the reader's other devices care
+                let user_ids = if payload.members.len() > state.config.server.receipts_max_members {
+                    HashSet::from([marker.user_id as u64])
+                } else {
+                    payload.members.iter().map(|v| *v as u64).collect()
+                };
+                Ok(vec![Self::new(
+                    user_ids,
+                    AppEvent::ReadMarkerUpdated(marker),
+                )])
             }
             _ => Err(anyhow::anyhow!("Invalid notification type")),
         }
     }
 }
 
-fn get_affected_chat_user_ids(old: Option<&Chat>, new: Option<&Chat>) -> HashSet<u64> {
-    match (old, new) {
-        (Some(old), Some(new)) => {
-            // diff old/new members, if identical, no need to notify, otherwise notify the union of both
-            let old_user_ids: HashSet<_> = old.members.iter().map(|v| *v as u64).collect();
-            let new_user_ids: HashSet<_> = new.members.iter().map(|v| *v as u64).collect();
-            if old_user_ids == new_user_ids {
-                HashSet::new()
-            } else {
-                old_user_ids.union(&new_user_ids).copied().collect()
-            }
+/// The message as chat_server returns it, with the client id so the sender can match it.
+async fn load_message(pool: &PgPool, id: i64) -> anyhow::Result<Option<Message>> {
+    let message = sqlx::query_as(
+        r#"
+        SELECT v.id, v.chat_id, v.sender_id, v.content, v.content_html, v.files, v.created_at,
+          v.edited_at, v.deleted_at, v.parent_id, v.reply_count, v.last_reply_at, v.expires_at,
+          v.reference, m.client_id, message_reactions_json(v.id) AS reactions
+        FROM visible_messages v
+        JOIN messages m ON m.id = v.id
+        WHERE v.id = $1
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(message)
+}
+
+fn member_ids(chat: &Chat) -> HashSet<u64> {
+    chat.members.iter().map(|v| *v as u64).collect()
+}
+
+fn get_chat_update_notifications(old: Chat, new: Chat) -> Vec<Notification> {
+    let old_user_ids = member_ids(&old);
+    let new_user_ids = member_ids(&new);
+    if old_user_ids == new_user_ids {
+        if old.name == new.name {
+            return vec![];
         }
-        (Some(old), None) => old.members.iter().map(|v| *v as u64).collect(),
-        (None, Some(new)) => new.members.iter().map(|v| *v as u64).collect(),
-        _ => HashSet::new(),
+        return vec![Notification::new(
+            new_user_ids,
+            AppEvent::ChatNameUpdated(new),
+        )];
+    }
+    let removed: HashSet<_> = old_user_ids.difference(&new_user_ids).copied().collect();
+    let mut ret = vec![];
+    if !removed.is_empty() {
+        ret.push(Notification::new(
+            removed,
+            AppEvent::RemoveFromChat(new.clone()),
+        ));
     }
+    ret.push(Notification::new(new_user_ids, AppEvent::AddToChat(new)));
+    ret
 }
 
 pub async fn setup_pg_listener(state: AppState) -> anyhow::Result<()> {
@@ -101,19 +193,35 @@ pub async fn setup_pg_listener(state: AppState) -> anyhow::Result<()> {
                         warn!("Failed to listen on chat_message_created: {:?}", e);
                         continue;
                     }
+                    if let Err(e) = listener
+                        .listen_all([
+                            "chat_message_updated",
+                            "chat_message_deleted",
+                            "message_reaction_changed",
+                            "read_marker_updated",
+                            "message_mentioned",
+                            "chat_pins_updated",
+                        ])
+                        .await
+                    {
+                        warn!("Failed to listen on message changes: {:?}", e);
+                        continue;
+                    }
                     let mut stream = listener.into_stream();
                     while let Some(Ok(notif)) = stream.next().await {
                         info!("Received notification: {:?}", notif);
-                        match Notification::load(notif.channel(), notif.payload()) {
-                            Ok(notification) => {
+                        match Notification::load(notif.channel(), notif.payload(), &state).await {
+                            Ok(notifications) => {
                                 let users = &state.users;
-                                for user_id in notification.user_ids {
-                                    if let Some(tx) = users.get(&user_id) {
-                                        if let Err(e) = tx.send(notification.event.clone()) {
-                                            warn!(
-                                                "Failed to send notification to user {}: {}",
-                                                user_id, e
-                                            );
+                                for notification in notifications {
+                                    for user_id in notification.user_ids {
+                                        if let Some(tx) = users.get(&user_id) {
+                                            if let Err(e) = tx.send(notification.event.clone()) {
+                                                warn!(
+                                                    "Failed to send notification to user {}: {}",
+                                                    user_id, e
+                                                );
+                                            }
                                         }
                                     }
                                 }
@@ -129,3 +237,143 @@ pub async fn setup_pg_listener(state: AppState) -> anyhow::Result<()> {
     });
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppConfig;
+    use anyhow::Result;
+    use std::time::Duration;
+
+    async fn listen(state: &AppState) -> Result<PgListener> {
+        let mut listener = PgListener::connect(&state.config.server.db_url).await?;
+        listener
+            .listen_all([
+                "chat_message_created",
+                "chat_message_updated",
+                "chat_message_deleted",
+                "message_reaction_changed",
+                "read_marker_updated",
+                "message_mentioned",
+                "chat_pins_updated",
+            ])
+            .await?;
+        Ok(listener)
+    }
+
+    /// Run the statement and load the notification it sent on the channel.
+    async fn notify(
+        state: &AppState,
+        listener: &mut PgListener,
+        channel: &str,
+        sql: &str,
+    ) -> Result<Vec<Notification>> {
+        sqlx::raw_sql(sql).execute(&state.pool).await?;
+        loop {
+            let notif = time::timeout(Duration::from_secs(5), listener.recv()).await??;
+            if notif.channel() == channel {
+                return Notification::load(channel, notif.payload(), state).await;
+            }
+        }
+    }
+
+    fn ids(user_ids: &[u64]) -> HashSet<u64> {
+        user_ids.iter().copied().collect()
+    }
+
+    #[tokio::test]
+    async fn message_notifications_should_load_the_message() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut listener = listen(&state).await?;
+
+        let sql = r#"
+            INSERT INTO messages (chat_id, sender_id, content, content_html, client_id)
+            VALUES (1, 2, 'hello', '<p>hello</p>', 'c1')
+        "#;
+        let ret = notify(&state, &mut listener, "chat_message_created", sql).await?;
+        assert_eq!(ret.len(), 1);
+        assert_eq!(ret[0].user_ids, ids(&[1, 2, 3, 4, 5]));
+        let AppEvent::NewMessage(message) = ret[0].event.as_ref() else {
+            panic!("expected NewMessage, got {:?}", ret[0].event);
+        };
+        assert_eq!(message.content, "hello");
+        assert_eq!(message.content_html, "<p>hello</p>");
+        assert_eq!(message.client_id.as_deref(), Some("c1"));
+        let id = message.id;
+
+        let sql = format!(
+            "UPDATE messages SET content = 'edited', content_html = '<p>edited</p>', edited_at = now() WHERE id = {id}"
+        );
+        let ret = notify(&state, &mut listener, "chat_message_updated", &sql).await?;
+        let AppEvent::MessageUpdated(message) = ret[0].event.as_ref() else {
+            panic!("expected MessageUpdated, got {:?}", ret[0].event);
+        };
+        assert_eq!(message.content, "edited");
+
+        // only the mentioned users are notified
+        let sql = format!("INSERT INTO message_mentions (message_id, user_id) VALUES ({id}, 3)");
+        let ret = notify(&state, &mut listener, "message_mentioned", &sql).await?;
+        assert_eq!(ret[0].user_ids, ids(&[3]));
+        assert!(matches!(ret[0].event.as_ref(), AppEvent::Mentioned(m) if m.id == id));
+
+        let sql = format!(
+            "UPDATE messages SET content = '', content_html = '', deleted_at = now() WHERE id = {id}"
+        );
+        let ret = notify(&state, &mut listener, "chat_message_deleted", &sql).await?;
+        let AppEvent::MessageDeleted(message) = ret[0].event.as_ref() else {
+            panic!("expected MessageDeleted, got {:?}", ret[0].event);
+        };
+        assert!(message.deleted_at.is_some());
+        assert!(message.content.is_empty());
+
+        // the message may be purged before the notification is handled
+        let payload = r#"{"messageId": 1000, "members": [1, 2]}"#;
+        let ret = Notification::load("chat_message_updated", payload, &state).await?;
+        assert!(ret.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_notifications_should_be_loaded() -> Result<()> {
+        let (tdb, state) = AppState::new_for_test().await?;
+        let mut listener = listen(&state).await?;
+
+        let sql = "INSERT INTO message_reactions (message_id, user_id, emoji) VALUES (1, 2, '👍')";
+        let ret = notify(&state, &mut listener, "message_reaction_changed", sql).await?;
+        assert_eq!(ret[0].user_ids, ids(&[1, 2, 3, 4, 5]));
+        let AppEvent::ReactionChanged(reaction) = ret[0].event.as_ref() else {
+            panic!("expected ReactionChanged, got {:?}", ret[0].event);
+        };
+        assert!(reaction.added);
+        assert_eq!(reaction.reactions[0].user_ids, vec![2]);
+
+        let sql = "INSERT INTO chat_pins (chat_id, message_id, pinned_by) VALUES (2, 1, 2)";
+        let ret = notify(&state, &mut listener, "chat_pins_updated", sql).await?;
+        assert_eq!(ret[0].user_ids, ids(&[1, 2, 3]));
+        let AppEvent::PinsUpdated(pins) = ret[0].event.as_ref() else {
+            panic!("expected PinsUpdated, got {:?}", ret[0].event);
+        };
+        assert_eq!(pins.chat_id, 2);
+        assert_eq!(pins.pins.len(), 1);
+
+        let sql =
+            "UPDATE chat_members SET last_read_message_id = 5 WHERE chat_id = 1 AND user_id = 2";
+        let ret = notify(&state, &mut listener, "read_marker_updated", sql).await?;
+        assert_eq!(ret[0].user_ids, ids(&[1, 2, 3, 4, 5]));
+        assert!(matches!(
+            ret[0].event.as_ref(),
+            AppEvent::ReadMarkerUpdated(m) if m.user_id == 2 && m.last_read_message_id == Some(5)
+        ));
+
+        // large chats only tell the reader's other devices
+        let mut config = AppConfig::load()?;
+        config.server.db_url = tdb.url();
+        config.server.receipts_max_members = 4;
+        let state = AppState::try_new(config).await?;
+        let sql =
+            "UPDATE chat_members SET last_read_message_id = 6 WHERE chat_id = 1 AND user_id = 3";
+        let ret = notify(&state, &mut listener, "read_marker_updated", sql).await?;
+        assert_eq!(ret[0].user_ids, ids(&[3]));
+        Ok(())
+    }
+}
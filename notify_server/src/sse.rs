@@ -3,7 +3,7 @@ use axum::{
     response::{sse::Event, Sse},
     Extension,
 };
-use chat_core::{Chat, Message, User};
+use chat_core::{Chat, Message, PinsUpdated, ReactionChanged, ReadMarker, User};
 
 use futures::Stream;
 use jwt_simple::reexports::serde_json;
@@ -43,6 +43,12 @@ pub enum AppEvent {
     AddToChat(Chat),
     RemoveFromChat(Chat),
     NewMessage(Message),
+    MessageUpdated(Message),
+    MessageDeleted(Message),
+    ReactionChanged(ReactionChanged),
+    ReadMarkerUpdated(ReadMarker),
+    Mentioned(Message),
+    PinsUpdated(PinsUpdated),
     ChatNameUpdated(Chat),
 }
 #[pin_project]
@@ -86,6 +92,12 @@ pub(crate) async fn sse_handler(
             AppEvent::AddToChat(_) => "AddToChat",
             AppEvent::RemoveFromChat(_) => "RemoveFromChat",
             AppEvent::NewMessage(_) => "NewMessage",
+            AppEvent::MessageUpdated(_) => "MessageUpdated",
+            AppEvent::MessageDeleted(_) => "MessageDeleted",
+            AppEvent::ReactionChanged(_) => "ReactionChanged",
+            AppEvent::ReadMarkerUpdated(_) => "ReadMarkerUpdated",
+            AppEvent::Mentioned(_) => "Mentioned",
+            AppEvent::PinsUpdated(_) => "PinsUpdated",
             AppEvent::ChatNameUpdated(_) => "ChatNameUpdated",
         };
         let v = serde_json::to_string(&v).expect("Failed to serialize event");
@@ -101,3 +113,35 @@ pub(crate) async fn sse_handler(
             .text("keep-alive-text"),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn app_event_should_be_tagged() -> Result<()> {
+        let event = AppEvent::ReadMarkerUpdated(ReadMarker {
+            chat_id: 1,
+            user_id: 2,
+            last_read_message_id: Some(5),
+        });
+        let v: serde_json::Value = serde_json::to_value(&event)?;
+        assert_eq!(
+            v,
+            serde_json::json!({
+                "event": "ReadMarkerUpdated",
+                "chatId": 1,
+                "userId": 2,
+                "lastReadMessageId": 5,
+            })
+        );
+        let event: AppEvent = serde_json::from_value(serde_json::json!({
+            "event": "PinsUpdated",
+            "chatId": 1,
+            "pins": [],
+        }))?;
+        assert!(matches!(event, AppEvent::PinsUpdated(p) if p.chat_id == 1));
+        Ok(())
+    }
+}